@@ -0,0 +1,23 @@
+//! feeds the decoder in chunks sized by the first byte, like a slow or chatty client
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lung::shared::RequestDecoder;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&chunk, data)) = data.split_first() else {
+        return;
+    };
+    let mut decoder = RequestDecoder::new();
+    for piece in data.chunks(chunk.max(1) as usize) {
+        decoder.feed(piece);
+        loop {
+            match decoder.next_request() {
+                Ok(Some(_)) => continue,
+                Ok(None) => break,
+                Err(_) => return,
+            }
+        }
+    }
+    let _ = decoder.finish();
+});
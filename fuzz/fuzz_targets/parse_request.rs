@@ -0,0 +1,13 @@
+//! anything that parses has to serialize back into something that parses the same way
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lung::shared::Request;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(request) = Request::try_from(data) {
+        let bytes = request.to_bytes();
+        let reparsed = Request::try_from(bytes.as_slice()).expect("serialized request must parse");
+        assert_eq!(reparsed.to_bytes(), bytes);
+    }
+});
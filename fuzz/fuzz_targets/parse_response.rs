@@ -0,0 +1,11 @@
+//! responses and the offline message batches inside them come from other servers
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lung::shared::{MessageBatch, Response};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(response) = Response::try_from(data) {
+        let _ = MessageBatch::try_from(&response);
+    }
+});
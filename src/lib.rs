@@ -1,4 +1,3 @@
 pub mod shared;
 pub mod server;
 pub use self::server::Server;
-static VERSION: &str = "lung/a0.1";
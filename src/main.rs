@@ -1,9 +1,42 @@
 // use lung::shared::crypt::go_through;
+use lung::{Server, server::identity::Identity, shared::spec};
+
+/// where `lung serve` keeps the server's key, relative to where it's started
+const IDENTITY_FILE: &str = "lung-identity";
 
 fn main() {
     // go_through();
     // let tok = lung::server::auth::Token::new("pebis".to_string(), 28);
     // let cipheretext = tok.encrypt(&derive_key("yo"));
     // println!("{:?}", shared)
-    // Server::new("0.0.0.0:1337").listen();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["spec"] | ["spec", "json"] => print!("{}", spec::json()),
+        ["spec", "markdown"] => print!("{}", spec::markdown()),
+        ["serve"] => serve("0.0.0.0:1337"),
+        ["serve", address] => serve(address),
+        _ => {
+            eprintln!("usage: lung spec [json|markdown]\n       lung serve [address]");
+            std::process::exit(2);
+        }
+    }
+}
+
+fn serve(address: &str) {
+    let result = Identity::load_or_generate(IDENTITY_FILE)
+        .map_err(|e| format!("can't load {IDENTITY_FILE}: {e}"))
+        .and_then(|identity| {
+            Server::new(address)
+                .and_then(|server| server.identity(identity).listen())
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
 }
@@ -1,16 +1,50 @@
-use crate::shared::Request;
+use crate::shared::{Request, crypt::password::Credential};
 
 #[derive(Debug)]
 pub enum DbError {
     UserNotFound,
     SessionNotFound,
+    /// a connection panicked while holding a lock, whatever it guarded may be half written
+    Poisoned,
 }
 
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbError::UserNotFound => write!(f, "user not found"),
+            DbError::SessionNotFound => write!(f, "session not found"),
+            DbError::Poisoned => write!(f, "database lock poisoned"),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+/// shared between every connection the server is handling, so everything goes through
+/// `&self` and implementations do their own locking
 pub trait SuitableDB: Send + Sync {
-    fn store_client(&mut self, user: String, hash: String);
-    fn check_client_auth(&self, user: &str, hash: &str) -> bool;
+    /// `credential` is a [Credential] written out, also when it replaces an older one
+    fn store_client(&self, user: String, credential: String) -> Result<(), DbError>;
+    fn get_client(&self, user: &str) -> Result<Option<String>, DbError>;
+    /// stores `credential` only if `user`'s entry is still `expected`, and says whether
+    /// it did. this has to be one step, connections upgrading the same entry at once
+    /// would hand out salts that aren't the stored one otherwise
+    fn replace_client(
+        &self,
+        user: &str,
+        expected: &str,
+        credential: String,
+    ) -> Result<bool, DbError>;
+    /// whether `proof` proves `user`'s password for `nonce`, see
+    /// [crate::shared::crypt::password]. unknown users and unreadable entries never do
+    fn check_client_auth(&self, user: &str, nonce: &str, proof: &str) -> Result<bool, DbError> {
+        Ok(self
+            .get_client(user)?
+            .and_then(|stored| stored.parse::<Credential>().ok())
+            .is_some_and(|credential| credential.verify(nonce, proof)))
+    }
     fn store_req_for_user(&self, user: String, req: Request) -> Result<(), DbError>;
-    fn fetch_reqs_for_user(&self, user: &str) -> Option<Vec<Request>>;
+    fn fetch_reqs_for_user(&self, user: &str) -> Result<Option<Vec<Request>>, DbError>;
     fn store_session(&self, user: String, id: String) -> Result<(), DbError>;
-    fn get_session(&self, user: &str) -> Option<String>;
+    fn get_session(&self, user: &str) -> Result<Option<String>, DbError>;
 }
@@ -0,0 +1,148 @@
+//! the server's long-term x25519 key. it's what clients pin on first contact, so it has
+//! to survive restarts. stored as a single line, `x25519 BASE64(secret)`
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::shared::crypt::traffic::gen_keys;
+
+const ALGO: &str = "x25519";
+
+#[derive(Clone)]
+pub struct Identity {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl Identity {
+    /// a fresh key, gone when the server stops
+    pub fn generate() -> Self {
+        let (secret, public) = gen_keys();
+        Self { secret, public }
+    }
+
+    /// loads the key at `path`, or generates one and saves it there if there's no file yet
+    pub fn load_or_generate(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::decode(&contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let identity = Self::generate();
+                identity.save(path)?;
+                Ok(identity)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// writes the key next to `path` first and moves it in place, so a crash can't
+    /// leave half a key behind. only the owner may read it
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let temp = path.with_extension("tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(&temp)?;
+        writeln!(file, "{ALGO} {}", STANDARD.encode(self.secret.as_bytes()))?;
+        file.sync_all()?;
+        fs::rename(temp, path)
+    }
+
+    fn decode(contents: &str) -> io::Result<Self> {
+        let invalid = |why: &str| io::Error::new(io::ErrorKind::InvalidData, why.to_string());
+        let (algo, key) = contents
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| invalid("identity file should be `x25519 [key]`"))?;
+        if algo != ALGO {
+            return Err(invalid("identity key isn't x25519"));
+        }
+        let bytes: [u8; 32] = STANDARD
+            .decode(key)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| invalid("identity key isn't 32 bytes of base64"))?;
+        let secret = StaticSecret::from(bytes);
+        let public = PublicKey::from(&secret);
+        Ok(Self { secret, public })
+    }
+
+    pub fn algo(&self) -> &'static str {
+        ALGO
+    }
+
+    pub fn secret(&self) -> &StaticSecret {
+        &self.secret
+    }
+
+    pub fn public(&self) -> &PublicKey {
+        &self.public
+    }
+
+    /// the key session tokens are sealed with. it's derived from the identity, so
+    /// sessions stay valid across restarts as long as the identity does
+    pub fn token_key(&self) -> [u8; 32] {
+        let hk = Hkdf::<Sha256>::new(None, self.secret.as_bytes());
+        let mut key = [0u8; 32];
+        hk.expand(b"lung/a0.1 token", &mut key)
+            .expect("this should never panic");
+        key
+    }
+
+    /// the key nonces are signed with, so the server knows its own without keeping them
+    pub fn nonce_key(&self) -> [u8; 32] {
+        let hk = Hkdf::<Sha256>::new(None, self.secret.as_bytes());
+        let mut key = [0u8; 32];
+        hk.expand(b"lung/a0.1 nonce", &mut key)
+            .expect("this should never panic");
+        key
+    }
+
+    /// the public key as it's sent in a certificate
+    pub fn public_base64(&self) -> String {
+        STANDARD.encode(self.public.as_bytes())
+    }
+}
+
+// ===== tests =====
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn survives_a_restart() {
+        let path = std::env::temp_dir().join(format!("lung-identity-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let first = Identity::load_or_generate(&path).unwrap();
+        let second = Identity::load_or_generate(&path).unwrap();
+        assert_eq!(first.public(), second.public());
+        assert_eq!(first.public_base64().len(), 44);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn broken_files_are_errors() {
+        for contents in ["", "x25519", "ed25519 AAAA", "x25519 AAAA", "x25519 !!"] {
+            assert!(Identity::decode(contents).is_err(), "{contents:?}");
+        }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod stdimpl;
+pub mod identity;
 use rand::{distr::Alphanumeric, prelude::*};
 pub use stdimpl::Server;
 pub mod db;
@@ -1,16 +1,43 @@
 use std::{
     collections::HashMap,
+    fmt::Display,
     io::{Read, Write},
     net::{TcpListener, TcpStream},
-    sync::{Arc, Mutex},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hkdf::hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
 use crate::{
-    server::db::{DbError, SuitableDB},
-    shared::{Request, Response, StatusCode},
+    server::{
+        db::{DbError, SuitableDB},
+        identity::Identity,
+    },
+    shared::{
+        ContractError, HeaderKind, ParseLimits, ProtocolVersion, Request, RequestDecoder,
+        RequestKind, RequestKindSpec, Response, ResponseHeaderKind, ResponseKind, StatusCode,
+        channel,
+        crypt::{
+            Timestamp, Token,
+            password::{Credential, KdfVersion, SALT_LEN},
+            traffic::SecureChannel,
+        },
+        envelope,
+    },
 };
 
 // ===== database =====
+/// clones share the same tables
+#[derive(Default, Clone)]
 pub struct InMemory {
     users: Arc<Mutex<HashMap<String, String>>>,
     sessions: Arc<Mutex<HashMap<String, String>>>,
@@ -27,21 +54,40 @@ impl InMemory {
     }
 }
 
+/// a poisoned lock fails the one request that ran into it instead of the whole server
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>, DbError> {
+    mutex.lock().map_err(|_| DbError::Poisoned)
+}
+
 impl SuitableDB for InMemory {
-    fn store_client(&mut self, user: String, hash: String) {
-        self.users.lock().unwrap().insert(user.clone(), hash);
-        self.requests.lock().unwrap().entry(user).or_default();
+    fn store_client(&self, user: String, credential: String) -> Result<(), DbError> {
+        lock(&self.users)?.insert(user.clone(), credential);
+        lock(&self.requests)?.entry(user).or_default();
+        Ok(())
+    }
+
+    fn get_client(&self, user: &str) -> Result<Option<String>, DbError> {
+        Ok(lock(&self.users)?.get(user).cloned())
     }
 
-    fn check_client_auth(&self, user: &str, hash: &str) -> bool {
-        match self.users.lock().unwrap().get(user) {
-            Some(stored) => stored == hash,
-            None => false,
+    fn replace_client(
+        &self,
+        user: &str,
+        expected: &str,
+        credential: String,
+    ) -> Result<bool, DbError> {
+        let mut users = lock(&self.users)?;
+        match users.get_mut(user) {
+            Some(stored) if stored == expected => {
+                *stored = credential;
+                Ok(true)
+            }
+            _ => Ok(false),
         }
     }
 
     fn store_req_for_user(&self, user: String, req: Request) -> Result<(), DbError> {
-        let mut map = self.requests.lock().unwrap();
+        let mut map = lock(&self.requests)?;
         if let Some(queue) = map.get_mut(&user) {
             queue.push(req);
             Ok(())
@@ -50,86 +96,677 @@ impl SuitableDB for InMemory {
         }
     }
 
-    fn fetch_reqs_for_user(&self, user: &str) -> Option<Vec<Request>> {
-        let mut map = self.requests.lock().unwrap();
-        map.get_mut(user).map(|queue| std::mem::take(queue))
+    fn fetch_reqs_for_user(&self, user: &str) -> Result<Option<Vec<Request>>, DbError> {
+        let mut map = lock(&self.requests)?;
+        Ok(map.get_mut(user).map(std::mem::take))
     }
 
     fn store_session(&self, user: String, id: String) -> Result<(), DbError> {
-        if self.users.lock().unwrap().contains_key(&user) {
-            self.sessions.lock().unwrap().insert(user, id);
+        if lock(&self.users)?.contains_key(&user) {
+            lock(&self.sessions)?.insert(user, id);
             Ok(())
         } else {
             Err(DbError::UserNotFound)
         }
     }
 
-    fn get_session(&self, user: &str) -> Option<String> {
-        self.sessions.lock().unwrap().get(user).cloned()
+    fn get_session(&self, user: &str) -> Result<Option<String>, DbError> {
+        Ok(lock(&self.sessions)?.get(user).cloned())
+    }
+}
+
+// ===== auth =====
+/// how long a nonce can be used for
+pub const NONCE_LIFETIME: Duration = Duration::from_secs(60);
+/// how long a session from hash auth lasts
+pub const SESSION_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// nonces aren't kept when they're handed out: one carries when it runs out and a mac
+/// over that and the client, so asking for any number of them can't push out anyone
+/// else's. only the ones that logged someone in are remembered, until they run out
+#[derive(Default)]
+struct UsedNonces {
+    used: Mutex<HashMap<String, Timestamp>>,
+}
+
+impl UsedNonces {
+    /// marks `nonce` as used, false if it already was
+    fn use_up(&self, nonce: &str, until: Timestamp) -> Result<bool, DbError> {
+        let mut used = lock(&self.used)?;
+        let now = unix_now();
+        used.retain(|_, until| *until >= now);
+        Ok(used.insert(nonce.to_string(), until).is_none())
+    }
+}
+
+const NONCE_RANDOM_LEN: usize = 16;
+
+/// `[until (8, big endian)][random][hmac sha256 of those and the client]`
+fn issue_nonce(identity: &Identity, client: &str, until: Timestamp) -> String {
+    let mut raw = until.to_be_bytes().to_vec();
+    raw.extend_from_slice(&rand::random::<[u8; NONCE_RANDOM_LEN]>());
+    let mac = nonce_mac(identity, &raw, client).finalize().into_bytes();
+    raw.extend_from_slice(&mac);
+    STANDARD.encode(raw)
+}
+
+/// when `nonce` runs out, if this server made it for `client`
+fn nonce_until(identity: &Identity, client: &str, nonce: &str) -> Option<Timestamp> {
+    let raw = STANDARD.decode(nonce).ok()?;
+    if raw.len() != 8 + NONCE_RANDOM_LEN + 32 {
+        return None;
+    }
+    let (signed, mac) = raw.split_at(8 + NONCE_RANDOM_LEN);
+    nonce_mac(identity, signed, client).verify_slice(mac).ok()?;
+    let until = signed.first_chunk::<8>()?;
+    Some(Timestamp::from_be_bytes(*until))
+}
+
+fn nonce_mac(identity: &Identity, signed: &[u8], client: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&identity.nonce_key())
+        .expect("hmac takes keys of any length");
+    mac.update(signed);
+    mac.update(client.as_bytes());
+    mac
+}
+
+/// envelopes remembered at once. they're forgotten after twice [envelope::FRESHNESS],
+/// past that their timestamp can't pass anymore
+const MAX_SEEN_ENVELOPES: usize = 1 << 16;
+
+/// ephemeral keys of the envelopes opened lately, so none is answered twice
+#[derive(Default)]
+struct SeenEnvelopes {
+    seen: Mutex<HashMap<[u8; 32], Instant>>,
+}
+
+impl SeenEnvelopes {
+    /// whether the envelope is new. it counts as seen after, and when there's no room
+    /// to remember it nothing new is let through
+    fn first_time(&self, ephemeral: [u8; 32]) -> Result<bool, DbError> {
+        let mut seen = lock(&self.seen)?;
+        if seen.len() >= MAX_SEEN_ENVELOPES {
+            seen.retain(|_, opened| opened.elapsed() < 2 * envelope::FRESHNESS);
+        }
+        if seen.len() >= MAX_SEEN_ENVELOPES || seen.contains_key(&ephemeral) {
+            return Ok(false);
+        }
+        seen.insert(ephemeral, Instant::now());
+        Ok(true)
+    }
+}
+
+fn unix_now() -> Timestamp {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as Timestamp)
+}
+
+// ===== errors =====
+#[derive(Debug)]
+pub enum ServerError {
+    /// the address didn't resolve to anything
+    Address(std::io::Error),
+    Bind(std::io::Error),
+    /// a worker thread couldn't be started
+    Spawn(std::io::Error),
+    Db(DbError),
+    /// a handler built a response that doesn't fit its kind
+    Contract(ContractError),
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerError::Address(e) => write!(f, "invalid address: {e}"),
+            ServerError::Bind(e) => write!(f, "can't listen: {e}"),
+            ServerError::Spawn(e) => write!(f, "can't start a worker: {e}"),
+            ServerError::Db(e) => write!(f, "database error: {e}"),
+            ServerError::Contract(e) => write!(f, "handler broke the response contract: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+impl From<DbError> for ServerError {
+    fn from(e: DbError) -> Self {
+        ServerError::Db(e)
+    }
+}
+
+impl From<ContractError> for ServerError {
+    fn from(e: ContractError) -> Self {
+        ServerError::Contract(e)
     }
 }
 
 // ===== server =====
+/// connections served at once unless configured otherwise. connections stay open
+/// between requests and mostly wait on the network, so this is well above the core count
+pub const DEFAULT_WORKERS: usize = 32;
+/// how often an idle connection checks whether a new one is waiting for its worker
+const IDLE_POLL: Duration = Duration::from_millis(50);
+
+/// how long a connection may take before it's dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// between two reads while a request is coming in, and for the first request on a
+    /// new connection to start
+    pub read: Duration,
+    /// for writing a response, a client that doesn't read loses its connection
+    pub write: Duration,
+    /// from the first byte of a request to the last, so trickling bytes in doesn't help
+    pub request: Duration,
+    /// waiting for the next request on a connection that has been answered before. cut
+    /// short as soon as another connection is waiting for a worker
+    pub idle: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            read: Duration::from_secs(10),
+            write: Duration::from_secs(10),
+            request: Duration::from_secs(30),
+            idle: Duration::from_secs(300),
+        }
+    }
+}
+
 pub struct Server {
     address: std::net::SocketAddr,
-    db: InMemory,
+    workers: usize,
+    worker: Worker,
+}
+
+/// everything needed to answer requests. each worker thread gets a copy, the db is shared
+#[derive(Clone)]
+struct Worker {
+    db: Arc<dyn SuitableDB>,
+    identity: Arc<Identity>,
+    nonces: Arc<UsedNonces>,
+    envelopes: Arc<SeenEnvelopes>,
+    versions: Vec<ProtocolVersion>,
+    limits: ParseLimits,
+    timeouts: Timeouts,
+    allow_plaintext: bool,
+    /// connections accepted and not picked up by a worker yet
+    waiting: Arc<AtomicUsize>,
 }
 
 impl Server {
-    pub fn new<T: std::net::ToSocketAddrs>(addr: T) -> Self {
-        let mut s = Self {
-            address: addr.to_socket_addrs().unwrap().next().unwrap(),
-            db: InMemory::new(),
-        };
-        s.db.store_client(
-            "jebediah".into(),
+    pub fn new<T: std::net::ToSocketAddrs>(addr: T) -> Result<Self, ServerError> {
+        let address = addr
+            .to_socket_addrs()
+            .map_err(ServerError::Address)?
+            .next()
+            .ok_or_else(|| {
+                ServerError::Address(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "no addresses to listen on",
+                ))
+            })?;
+        let db = InMemory::new();
+        let jebediah = Credential::Legacy(
             "9f56e761d79bfdb34304a012586cb04d16b435ef6130091a97702e559260a2f2".into(),
         );
-        s
+        db.store_client("jebediah".into(), jebediah.upgrade().to_string())?;
+        Ok(Self {
+            address,
+            workers: DEFAULT_WORKERS,
+            worker: Worker {
+                db: Arc::new(db),
+                identity: Arc::new(Identity::generate()),
+                nonces: Arc::default(),
+                envelopes: Arc::default(),
+                versions: ProtocolVersion::SUPPORTED.to_vec(),
+                limits: ParseLimits::default(),
+                timeouts: Timeouts::default(),
+                allow_plaintext: false,
+                waiting: Arc::default(),
+            },
+        })
+    }
+
+    /// the database every connection reads and writes, defaults to an [InMemory] with
+    /// a test user
+    pub fn db(mut self, db: impl SuitableDB + 'static) -> Self {
+        self.worker.db = Arc::new(db);
+        self
+    }
+
+    /// the key clients pin on first contact. defaults to a fresh one, so anything that
+    /// should keep its clients across restarts wants [Identity::load_or_generate]
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.worker.identity = Arc::new(identity);
+        self
+    }
+
+    /// whether requests may skip the envelope, off by default. only for trying things
+    /// out locally, plaintext sessions and passwords can be read by anyone in between
+    pub fn allow_plaintext(mut self, allow: bool) -> Self {
+        self.worker.allow_plaintext = allow;
+        self
+    }
+
+    /// how many connections are served at once, defaults to [DEFAULT_WORKERS]. a
+    /// connection holds its worker while a request comes in and is answered. between
+    /// requests it's closed as soon as a new connection is waiting, so only requests in
+    /// progress and fresh connections that haven't asked anything yet (for at most
+    /// [Timeouts::read]) can keep others from being served
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// protocol versions to accept, defaults to [ProtocolVersion::SUPPORTED]
+    pub fn versions(mut self, versions: &[ProtocolVersion]) -> Self {
+        self.worker.versions = versions.to_vec();
+        self
     }
 
-    pub fn listen(mut self) {
-        let listener = TcpListener::bind(self.address).unwrap();
+    /// how big a request may get before it's refused with payload too large
+    pub fn limits(mut self, limits: ParseLimits) -> Self {
+        self.worker.limits = limits;
+        self
+    }
+
+    /// how long clients get before they're disconnected
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.worker.timeouts = timeouts;
+        self
+    }
+
+    /// serves until every worker is gone. broken connections are logged and dropped,
+    /// only failing to start up is an error
+    pub fn listen(self) -> Result<(), ServerError> {
+        let listener = TcpListener::bind(self.address).map_err(ServerError::Bind)?;
         println!("Listening on {}", self.address);
+        self.accept(listener)
+    }
+
+    /// hands connections to a fixed pool of workers. the queue holds no connections of
+    /// its own, so accepting blocks while every worker is busy
+    fn accept(self, listener: TcpListener) -> Result<(), ServerError> {
+        let (sender, receiver) = mpsc::sync_channel::<TcpStream>(0);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for id in 0..self.workers {
+            let receiver = Arc::clone(&receiver);
+            let worker = self.worker.clone();
+            thread::Builder::new()
+                .name(format!("lung-worker-{id}"))
+                .spawn(move || worker.work(&receiver))
+                .map_err(ServerError::Spawn)?;
+        }
 
         for stream in listener.incoming() {
             match stream {
-                Ok(mut stream) => {
-                    let mut buf = Vec::new();
-                    stream.read_to_end(&mut buf).unwrap();
-                    let request_text = String::from_utf8_lossy(&buf);
-
-                    println!("raw request: {}", request_text.to_string());
-                    match Request::try_from(request_text.to_string()) {
-                        Ok(request) => {
-                            println!("got request: {:#?}", request);
-                            self.process_request(stream, request);
-                        }
-                        Err(e) => {
-                            let _ = write_error(&mut stream, e.to_status_code(), e.inner());
-                        }
+                Ok(stream) => {
+                    // idle connections see this and make room
+                    self.worker.waiting.fetch_add(1, Ordering::SeqCst);
+                    let sent = sender.send(stream);
+                    self.worker.waiting.fetch_sub(1, Ordering::SeqCst);
+                    if sent.is_err() {
+                        eprintln!("Every worker is gone, shutting down");
+                        return Ok(());
                     }
                 }
                 Err(e) => eprintln!("Connection failed: {}", e),
             }
         }
+        Ok(())
     }
+}
+
+impl Worker {
+    /// serves connections off the queue until the server goes away
+    fn work(&self, receiver: &Mutex<mpsc::Receiver<TcpStream>>) {
+        loop {
+            let stream = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => return,
+            };
+            let Ok(stream) = stream else {
+                return;
+            };
+            // a bug in a handler takes down its connection, not the worker
+            match panic::catch_unwind(AssertUnwindSafe(|| self.serve(stream))) {
+                Ok(Err(e)) => eprintln!("Connection dropped: {}", e),
+                Err(_) => eprintln!("Connection dropped: handler panicked"),
+                Ok(Ok(())) => {}
+            }
+        }
+    }
+
+    /// answers requests on one connection until the client hangs up
+    fn serve(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_write_timeout(Some(self.timeouts.write))?;
+        let mut decoder = RequestDecoder::with_limits(self.limits);
+        let mut chunk = [0u8; 4096];
+        // when the request that's coming in started. a fresh connection counts as one,
+        // only a connection that has been answered gets to sit idle, or silent sockets
+        // would hold every worker for the idle timeout
+        let mut started: Option<Instant> = Some(Instant::now());
+        // when the last answer went out
+        let mut idle_since = Instant::now();
+        let mut channel: Option<SecureChannel> = None;
+        loop {
+            let wait = match started {
+                None => IDLE_POLL.min(self.timeouts.idle.saturating_sub(idle_since.elapsed())),
+                Some(started) => self
+                    .timeouts
+                    .read
+                    .min(self.timeouts.request.saturating_sub(started.elapsed())),
+            };
+            let n = match read_within(&mut stream, &mut chunk, wait) {
+                Ok(n) => n,
+                Err(e) if is_timeout(&e) => {
+                    // idle connections keep their worker until it's wanted elsewhere
+                    if started.is_none()
+                        && idle_since.elapsed() < self.timeouts.idle
+                        && self.waiting.load(Ordering::SeqCst) == 0
+                    {
+                        continue;
+                    }
+                    // nothing was asked, the connection is just closed
+                    if decoder.is_empty() {
+                        return Ok(());
+                    }
+                    return write_error(&mut stream, StatusCode::Timeout, "request took too long");
+                }
+                Err(e) => return Err(e),
+            };
+            if n > 0 {
+                decoder.feed(&chunk[..n]);
+            }
+
+            loop {
+                let next = if n == 0 {
+                    decoder.finish()
+                } else {
+                    decoder.next_request()
+                };
+                match next {
+                    Ok(Some(request)) => {
+                        self.process_request(&mut stream, request, &mut channel)?;
+                        started = None;
+                        idle_since = Instant::now();
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        // the decoder can't find the next request after an error
+                        return write_error(&mut stream, e.to_status_code(), e.inner());
+                    }
+                }
+            }
+
+            if n == 0 {
+                return Ok(());
+            }
+            // whatever's left over is the start of the next request
+            if !decoder.is_empty() {
+                started.get_or_insert_with(Instant::now);
+            }
+        }
+    }
+
+    /// answers a request that came in on the connection. handshakes and frames are
+    /// about the connection's channel, everything else is answered by [Worker::respond]
+    fn process_request(
+        &self,
+        stream: &mut TcpStream,
+        request: Request,
+        channel: &mut Option<SecureChannel>,
+    ) -> std::io::Result<()> {
+        let version = request.version.negotiate(&self.versions);
+        let response = match (&request.kind, version, channel.as_mut()) {
+            (RequestKind::Handshake, Some(version), _) => {
+                match channel::accept(&request, self.identity.secret()) {
+                    Ok((opened, response)) => {
+                        *channel = Some(opened);
+                        Response {
+                            version,
+                            ..response
+                        }
+                    }
+                    Err(e) => error_response(e.to_status_code(), e.inner()),
+                }
+            }
+            (RequestKind::Frame, Some(_), Some(open)) => {
+                let inner = match channel::open_frame(open, &request) {
+                    Ok(inner) => inner,
+                    Err(e) => {
+                        // the counters can't be trusted anymore
+                        write_error(&mut *stream, e.to_status_code(), e.inner())?;
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "frame doesn't decrypt",
+                        ));
+                    }
+                };
+                let response = match Request::parse_with_limits(&inner, &self.limits) {
+                    Ok(inner) => self.respond(inner, true),
+                    Err(e) => error_response(e.to_status_code(), e.inner()),
+                };
+                channel::frame_reply(open, &response)
+                    .unwrap_or_else(|e| error_response(e.to_status_code(), e.inner()))
+            }
+            (RequestKind::Frame, Some(_), None) => {
+                error_response(StatusCode::BadRequest, "frames need a handshake first")
+            }
+            _ => self.respond(request, false),
+        };
+        stream.write_all(&response.to_bytes())
+    }
+
+    /// the answer to a request, errors included. `sealed` is whether it came out of an
+    /// envelope, anything but a certificate request has to unless plaintext is allowed
+    fn respond(&self, request: Request, sealed: bool) -> Response {
+        let kind = request.kind.clone();
+        let response = match request.version.negotiate(&self.versions) {
+            Some(version) => {
+                let handler: Handler = match kind {
+                    RequestKind::Certificate => handle_certificate,
+                    RequestKind::Encrypted if !sealed => handle_envelope,
+                    // handshakes and frames are taken care of by process_request
+                    RequestKind::Encrypted | RequestKind::Handshake | RequestKind::Frame => {
+                        handle_nested
+                    }
+                    _ if !sealed && !self.allow_plaintext => handle_plaintext,
+                    RequestKind::Nonce => handle_nonce,
+                    RequestKind::HashAuth => handle_hash_auth,
+                    _ => handler_nyi,
+                };
+                handler(request, self).map(|mut response| {
+                    response.version = version;
+                    response
+                })
+            }
+            None => {
+                let supported: Vec<String> = self.versions.iter().map(|v| v.to_string()).collect();
+                Response::build(ResponseKind::UnsupportedVersion)
+                    .header(ResponseHeaderKind::Version, supported.join(", "))
+                    .finish()
+                    .map_err(ServerError::from)
+            }
+        };
+
+        match response {
+            Ok(response) => {
+                debug_assert!(
+                    ResponseKind::from_status(response.status)
+                        .is_ok_and(|response_kind| kind.allows_response(response_kind)),
+                    "{} can't be answered with {:?}",
+                    kind,
+                    response.status
+                );
+                response
+            }
+            Err(e) => {
+                eprintln!("Failed to handle {}: {}", kind, e);
+                error_response(StatusCode::InternalError, "")
+            }
+        }
+    }
+}
 
-    fn process_request(&mut self, stream: TcpStream, request: Request) {
-        (match request.kind {
-            _ => handler_nyi,
-        })(stream, request, &mut self.db);
+/// a read that gives up after `wait`. a zero wait has already run out
+fn read_within(stream: &mut TcpStream, buf: &mut [u8], wait: Duration) -> std::io::Result<usize> {
+    if wait.is_zero() {
+        return Err(std::io::ErrorKind::TimedOut.into());
     }
+    stream.set_read_timeout(Some(wait))?;
+    stream.read(buf)
 }
 
-fn handler_nyi(mut stream: TcpStream, _req: Request, _db: &mut InMemory) {
-    let _ = stream.write_all(
-        Response::new(StatusCode::Teapot)
-            .header(crate::shared::ResponseHeaderKind::Ok, "true")
-            .body("hello! this is an example response")
-            .to_string()
-            .as_bytes(),
-    );
+/// sockets report a timeout as either, depending on the platform
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+// ===== handlers =====
+type Handler = fn(Request, &Worker) -> Result<Response, ServerError>;
+
+fn handler_nyi(_req: Request, _worker: &Worker) -> Result<Response, ServerError> {
+    Ok(Response::build(ResponseKind::Unsupported)
+        .body("not yet implemented")
+        .finish()?)
+}
+
+/// decrypts the inner request, answers it and encrypts the answer back
+fn handle_envelope(req: Request, worker: &Worker) -> Result<Response, ServerError> {
+    let opened = match envelope::open(&req, worker.identity.secret()) {
+        Ok(opened) => opened,
+        // without the client's key there's nothing to encrypt an answer with
+        Err(e) => return Ok(error_response(e.to_status_code(), e.inner())),
+    };
+    // whoever replays it can't read the answer, but it would still be acted on
+    if !worker.envelopes.first_time(opened.ephemeral)? {
+        return Ok(error_response(
+            StatusCode::Denied,
+            "this envelope was already opened",
+        ));
+    }
+    let response = match Request::parse_with_limits(&opened.request, &worker.limits) {
+        Ok(inner) => worker.respond(inner, true),
+        Err(e) => error_response(e.to_status_code(), e.inner()),
+    };
+    Ok(envelope::seal_reply(&response, &opened.reply_key)?)
+}
+
+fn handle_nested(_req: Request, _worker: &Worker) -> Result<Response, ServerError> {
+    Ok(error_response(
+        StatusCode::BadRequest,
+        "encrypted requests can't be nested",
+    ))
+}
+
+fn handle_plaintext(_req: Request, _worker: &Worker) -> Result<Response, ServerError> {
+    Ok(error_response(
+        StatusCode::Denied,
+        "requests have to be encrypted with the certificate key",
+    ))
+}
+
+/// hands out a nonce with the salt to prove the password with. legacy entries are
+/// upgraded here, the client can't prove anything against them
+fn handle_nonce(req: Request, worker: &Worker) -> Result<Response, ServerError> {
+    let client = req
+        .headers
+        .get(&HeaderKind::Client)
+        .map_or("", String::as_str);
+    let read = |stored: Option<String>| stored.and_then(|stored| stored.parse().ok());
+    let stored = worker.db.get_client(client)?;
+    let credential = match read(stored.clone()) {
+        Some(legacy @ Credential::Legacy(_)) => {
+            let upgraded = legacy.upgrade();
+            let expected = stored.as_deref().unwrap_or_default();
+            if worker
+                .db
+                .replace_client(client, expected, upgraded.to_string())?
+            {
+                Some(upgraded)
+            } else {
+                // someone else changed it first, their salt is the one that counts
+                read(worker.db.get_client(client)?)
+            }
+        }
+        credential => credential,
+    };
+    let (kdf, salt) = match credential {
+        Some(Credential::Salted { kdf, salt, .. }) => (kdf, salt),
+        // unknown clients get a salt and a nonce all the same, or asking would tell who
+        // exists. nothing can be proven with the nonce anyway
+        _ => (KdfVersion::CURRENT, decoy_salt(worker, client)),
+    };
+    let until = unix_now() + NONCE_LIFETIME.as_secs() as Timestamp;
+    let nonce = issue_nonce(&worker.identity, client, until);
+    Ok(Response::build(ResponseKind::NonceGiven)
+        .header(ResponseHeaderKind::Nonce, nonce)
+        .header(ResponseHeaderKind::Until, until.to_string())
+        .header(ResponseHeaderKind::Salt, STANDARD.encode(salt))
+        .header(ResponseHeaderKind::Kdf, kdf.to_string())
+        .finish()?)
+}
+
+/// the same for a client every time, and only this server can make it
+fn decoy_salt(worker: &Worker, client: &str) -> [u8; SALT_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(worker.identity.token_key());
+    hasher.update(client);
+    let hash = hasher.finalize();
+    std::array::from_fn(|i| hash[i])
+}
+
+/// checks the proof against the nonce it names, and opens a session. a nonce logs in
+/// once, a wrong proof doesn't use it up
+fn handle_hash_auth(req: Request, worker: &Worker) -> Result<Response, ServerError> {
+    let client = req
+        .headers
+        .get(&HeaderKind::Client)
+        .map_or("", String::as_str);
+    let nonce = req
+        .headers
+        .get(&HeaderKind::Nonce)
+        .map_or("", String::as_str);
+    let hash = req
+        .headers
+        .get(&HeaderKind::Hash)
+        .map_or("", String::as_str);
+    let accepted = match nonce_until(&worker.identity, client, nonce) {
+        Some(until) if until >= unix_now() => {
+            worker.db.check_client_auth(client, nonce, hash)?
+                && worker.nonces.use_up(nonce, until)?
+        }
+        _ => false,
+    };
+    if !accepted {
+        return Ok(Response::build(ResponseKind::HashInvalid).finish()?);
+    }
+
+    let until = unix_now() + SESSION_LIFETIME.as_secs() as Timestamp;
+    let token = Token::new(client.to_string(), until).encrypt(&worker.identity.token_key());
+    let session = STANDARD.encode(token);
+    worker
+        .db
+        .store_session(client.to_string(), session.clone())?;
+    Ok(Response::build(ResponseKind::HashAccepted)
+        .header(ResponseHeaderKind::Ok, "true")
+        .header(ResponseHeaderKind::Session, session)
+        .header(ResponseHeaderKind::Until, until.to_string())
+        .finish()?)
+}
+
+/// tofu, the client pins whatever key it gets the first time
+fn handle_certificate(_req: Request, worker: &Worker) -> Result<Response, ServerError> {
+    Ok(Response::build(ResponseKind::CertificateGiven)
+        .header(ResponseHeaderKind::Algo, worker.identity.algo())
+        .header(ResponseHeaderKind::Pubkey, worker.identity.public_base64())
+        .finish()?)
 }
 
 fn write_error(
@@ -137,5 +774,489 @@ fn write_error(
     code: StatusCode,
     message: impl Into<String>,
 ) -> Result<(), std::io::Error> {
-    writer.write_all(Response::with_body(code, message).to_string().as_bytes())
+    writer.write_all(&error_response(code, message).to_bytes())
+}
+
+fn error_response(code: StatusCode, message: impl Into<String>) -> Response {
+    let message = message.into();
+    let kind = ResponseKind::from_status(code).unwrap_or(ResponseKind::InternalError);
+    let builder = Response::build(kind);
+    let builder = if message.is_empty() {
+        builder
+    } else {
+        builder.body(message)
+    };
+    builder.finish().unwrap_or_else(|_| {
+        Response::build(ResponseKind::InternalError)
+            .finish()
+            .expect("a bare internal error is always valid")
+    })
+}
+
+// ===== tests =====
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{ResponseDecoder, crypt::password};
+
+    /// starts `server` on a free port
+    fn spawn(configure: impl FnOnce(Server) -> Server) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = configure(Server::new(address).unwrap());
+        thread::spawn(move || server.accept(listener));
+        address
+    }
+
+    #[test]
+    fn slow_clients_dont_block_others() {
+        let address = spawn(|server| server.workers(2));
+
+        // half a request that never gets finished
+        let mut slow = TcpStream::connect(address).unwrap();
+        slow.write_all(b"lung/a0.1 send\nto: bo").unwrap();
+
+        let mut fast = TcpStream::connect(address).unwrap();
+        fast.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        fast.write_all(b"lung/a0.1 certificate\n\n").unwrap();
+        let reply = read_response(&mut fast, &mut ResponseDecoder::new());
+        assert_eq!(reply.status, StatusCode::CertificateGiven);
+    }
+
+    #[test]
+    fn poisoned_db_fails_requests_not_the_server() {
+        let db = InMemory::new();
+        db.store_client("jerma".into(), "hash".into()).unwrap();
+        let users = Arc::clone(&db.users);
+        let _ = thread::spawn(move || {
+            let _guard = users.lock().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(matches!(
+            db.check_client_auth("jerma", "nonce", "hash"),
+            Err(DbError::Poisoned)
+        ));
+        // other tables keep working
+        assert!(db.fetch_reqs_for_user("jerma").unwrap().is_some());
+    }
+
+    #[test]
+    fn bad_addresses_are_errors() {
+        assert!(matches!(
+            Server::new("definitely not an address"),
+            Err(ServerError::Address(_))
+        ));
+    }
+
+    #[test]
+    fn trickling_requests_time_out() {
+        let address = spawn(|server| {
+            server.timeouts(Timeouts {
+                read: Duration::from_millis(200),
+                request: Duration::from_millis(300),
+                ..Timeouts::default()
+            })
+        });
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // every byte arrives well within the read timeout, the request never finishes
+        let mut reply = Vec::new();
+        for byte in b"lung/a0.1 send\nto: bobby#s1\n" {
+            if client.write_all(&[*byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let _ = client.read_to_end(&mut reply);
+        assert!(reply.starts_with(b"lung/a0.1 status -14: timeout"));
+    }
+
+    #[test]
+    fn idle_connections_are_closed() {
+        let address = spawn(|server| {
+            server.timeouts(Timeouts {
+                idle: Duration::from_millis(100),
+                ..Timeouts::default()
+            })
+        });
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.write_all(b"lung/a0.1 certificate\n\n").unwrap();
+        let reply = read_response(&mut client, &mut ResponseDecoder::new());
+        assert_eq!(reply.status, StatusCode::CertificateGiven);
+        let mut rest = Vec::new();
+        assert_eq!(client.read_to_end(&mut rest).unwrap(), 0);
+    }
+
+    #[test]
+    fn idle_connections_make_room() {
+        let address = spawn(|server| server.workers(2));
+        // one more than there are workers, each asks once and then stays open
+        let mut idle = Vec::new();
+        for _ in 0..3 {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            stream.write_all(b"lung/a0.1 certificate\n\n").unwrap();
+            let reply = read_response(&mut stream, &mut ResponseDecoder::new());
+            assert_eq!(reply.status, StatusCode::CertificateGiven);
+            idle.push(stream);
+        }
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.write_all(b"lung/a0.1 certificate\n\n").unwrap();
+        let reply = read_response(&mut client, &mut ResponseDecoder::new());
+        assert_eq!(reply.status, StatusCode::CertificateGiven);
+    }
+
+    #[test]
+    fn silent_connections_dont_hold_workers() {
+        let address = spawn(|server| {
+            server.workers(2).timeouts(Timeouts {
+                read: Duration::from_millis(200),
+                ..Timeouts::default()
+            })
+        });
+        // one more than there are workers, none of them ever says anything
+        let _silent: Vec<_> = (0..3)
+            .map(|_| TcpStream::connect(address).unwrap())
+            .collect();
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.write_all(b"lung/a0.1 certificate\n\n").unwrap();
+        let reply = read_response(&mut client, &mut ResponseDecoder::new());
+        assert_eq!(reply.status, StatusCode::CertificateGiven);
+    }
+
+    /// sends `request` on a fresh connection and reads the answer
+    fn exchange(address: std::net::SocketAddr, request: &[u8]) -> Response {
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(request).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).unwrap();
+        Response::try_from(reply.as_slice()).unwrap()
+    }
+
+    /// reads until `decoder` has the next response
+    fn read_response(stream: &mut TcpStream, decoder: &mut ResponseDecoder) -> Response {
+        let mut buf = [0u8; 4096];
+        loop {
+            if let Some(response) = decoder.next_response().unwrap() {
+                return response;
+            }
+            let n = stream.read(&mut buf).unwrap();
+            assert!(n > 0, "the connection closed before a whole response");
+            decoder.feed(&buf[..n]);
+        }
+    }
+
+    #[test]
+    fn pipelined_requests_get_one_reply_each() {
+        let address = spawn(|server| server.allow_plaintext(true));
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let auth = b"lung/a0.1 hash auth\nclient: jerma\nnonce: nope\nhash: nope\n\n";
+        stream.write_all(&[&auth[..], auth, auth].concat()).unwrap();
+
+        let mut decoder = ResponseDecoder::new();
+        for _ in 0..3 {
+            let reply = read_response(&mut stream, &mut decoder);
+            assert_eq!(reply.status, StatusCode::HashInvalid);
+        }
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn certificate_gives_the_identity_key() {
+        let identity = Identity::generate();
+        let expected = identity.public_base64();
+        let address = spawn(|server| server.identity(identity));
+
+        let response = exchange(address, b"lung/a0.1 certificate\n\n");
+        assert_eq!(response.status, StatusCode::CertificateGiven);
+        assert_eq!(
+            response.headers.get(&ResponseHeaderKind::Algo).unwrap(),
+            "x25519"
+        );
+        assert_eq!(
+            response.headers.get(&ResponseHeaderKind::Pubkey).unwrap(),
+            &expected
+        );
+        assert!(response.body.is_none());
+    }
+
+    #[test]
+    fn envelopes_are_opened_and_answered_encrypted() {
+        let identity = Identity::generate();
+        let server_pub = *identity.public();
+        let address = spawn(|server| server.identity(identity));
+
+        let request = Request::new(RequestKind::Certificate);
+        let (sealed, key) = envelope::seal(&request, &server_pub);
+        let reply = exchange(address, &sealed.to_bytes());
+        assert_eq!(reply.status, StatusCode::Encrypted);
+        let inner = envelope::open_reply(&reply, &key).unwrap();
+        assert_eq!(inner.status, StatusCode::CertificateGiven);
+
+        // the same envelope again is refused
+        let reply = exchange(address, &sealed.to_bytes());
+        assert_eq!(reply.status, StatusCode::Denied);
+
+        // an envelope in an envelope is refused, still encrypted
+        let (nested, _) = envelope::seal(&sealed, &server_pub);
+        let (outer, key) = envelope::seal(&nested, &server_pub);
+        let reply = exchange(address, &outer.to_bytes());
+        let inner = envelope::open_reply(&reply, &key).unwrap();
+        assert_eq!(inner.status, StatusCode::BadRequest);
+
+        // garbage can't be answered encrypted
+        let garbage = Request::new(RequestKind::Encrypted).body(vec![7u8; 64]);
+        assert_eq!(
+            exchange(address, &garbage.to_bytes()).status,
+            StatusCode::BadRequest
+        );
+    }
+
+    #[test]
+    fn plaintext_is_denied_unless_allowed() {
+        let request = b"lung/a0.1 hash auth\nclient: jerma\nnonce: abc\nhash: abc\n\n";
+        let address = spawn(|server| server);
+        assert_eq!(exchange(address, request).status, StatusCode::Denied);
+
+        let address = spawn(|server| server.allow_plaintext(true));
+        assert_ne!(exchange(address, request).status, StatusCode::Denied);
+    }
+
+    #[test]
+    fn channel_carries_requests_until_a_frame_is_replayed() {
+        let identity = Identity::generate();
+        let server_pub = *identity.public();
+        let address = spawn(|server| server.identity(identity));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut decoder = ResponseDecoder::new();
+        let mut read_response = |stream: &mut TcpStream| read_response(stream, &mut decoder);
+
+        let (hello, pending) = channel::handshake(&server_pub);
+        stream.write_all(&hello.to_bytes()).unwrap();
+        let mut client = pending.finish(&read_response(&mut stream)).unwrap();
+
+        let mut last = Vec::new();
+        for _ in 0..2 {
+            let frame = channel::frame(&mut client, &Request::new(RequestKind::Certificate))
+                .unwrap()
+                .to_bytes();
+            stream.write_all(&frame).unwrap();
+            let reply = read_response(&mut stream);
+            let inner = channel::open_reply(&mut client, &reply).unwrap();
+            assert_eq!(inner.status, StatusCode::CertificateGiven);
+            last = frame;
+        }
+
+        stream.write_all(&last).unwrap();
+        assert_eq!(read_response(&mut stream).status, StatusCode::BadRequest);
+        let mut rest = Vec::new();
+        assert_eq!(stream.read_to_end(&mut rest).unwrap(), 0);
+    }
+
+    /// asks for a nonce for `client`, and what to prove the password with
+    fn challenge(address: std::net::SocketAddr, client: &str) -> (String, KdfVersion, [u8; 16]) {
+        let response = exchange(
+            address,
+            format!("lung/a0.1 nonce\nclient: {client}\n\n").as_bytes(),
+        );
+        assert_eq!(response.status, StatusCode::NonceGiven);
+        let header = |kind| response.headers.get(&kind).unwrap().clone();
+        (
+            header(ResponseHeaderKind::Nonce),
+            header(ResponseHeaderKind::Kdf).parse().unwrap(),
+            password::parse_salt(&header(ResponseHeaderKind::Salt)).unwrap(),
+        )
+    }
+
+    #[test]
+    fn hash_auth_opens_a_session() {
+        let db = InMemory::new();
+        // from before the kdf, upgraded by the first nonce
+        let legacy = format!("{:x}", Sha256::digest("hunter2"));
+        db.store_client("jerma".into(), legacy.clone()).unwrap();
+        let identity = Identity::generate();
+        let token_key = identity.token_key();
+        let address = spawn({
+            let db = db.clone();
+            |server| server.db(db).identity(identity).allow_plaintext(true)
+        });
+        let auth = |nonce: &str, hash: &str| {
+            format!("lung/a0.1 hash auth\nclient: jerma\nnonce: {nonce}\nhash: {hash}\n\n")
+        };
+
+        // no nonce, no session
+        let response = exchange(address, auth("guess", &"0".repeat(64)).as_bytes());
+        assert_eq!(response.status, StatusCode::HashInvalid);
+
+        let (nonce, kdf, salt) = challenge(address, "jerma");
+        let stored = db.get_client("jerma").unwrap().unwrap();
+        assert!(stored.starts_with("v1$"), "{stored}");
+        assert!(!stored.contains(&legacy));
+        let proof = password::prove("hunter2", kdf, &salt, &nonce);
+        let response = exchange(address, auth(&nonce, &proof).as_bytes());
+        assert_eq!(response.status, StatusCode::HashAccepted);
+
+        let session = response.headers.get(&ResponseHeaderKind::Session).unwrap();
+        assert_eq!(db.get_session("jerma").unwrap().as_ref(), Some(session));
+        let token = Token::decrypt(STANDARD.decode(session).unwrap(), &token_key).unwrap();
+        assert_eq!(token.id(), "jerma");
+        let until: Timestamp = response
+            .headers
+            .get(&ResponseHeaderKind::Until)
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(token.until(), until);
+        assert!(until - unix_now() > 6 * 24 * 60 * 60);
+
+        // nonces are single use
+        let response = exchange(address, auth(&nonce, &proof).as_bytes());
+        assert_eq!(response.status, StatusCode::HashInvalid);
+
+        // the upgrade happens once, and a wrong password doesn't get in
+        let (nonce, kdf, again) = challenge(address, "jerma");
+        assert_eq!(again, salt);
+        let proof = password::prove("hunter3", kdf, &salt, &nonce);
+        let response = exchange(address, auth(&nonce, &proof).as_bytes());
+        assert_eq!(response.status, StatusCode::HashInvalid);
+    }
+
+    #[test]
+    fn concurrent_upgrades_agree_on_the_salt() {
+        let db = InMemory::new();
+        let legacy = format!("{:x}", Sha256::digest("hunter2"));
+        db.store_client("jerma".into(), legacy).unwrap();
+        let worker = Server::new("127.0.0.1:0").unwrap().db(db.clone()).worker;
+
+        let salts: Vec<String> = thread::scope(|scope| {
+            let asking: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let ask = Request::new(RequestKind::Nonce)
+                            .header(HeaderKind::Client, "jerma")
+                            .unwrap();
+                        let response = handle_nonce(ask, &worker).unwrap();
+                        response
+                            .headers
+                            .get(&ResponseHeaderKind::Salt)
+                            .unwrap()
+                            .clone()
+                    })
+                })
+                .collect();
+            asking.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        let Ok(Credential::Salted { salt, .. }) = db.get_client("jerma").unwrap().unwrap().parse()
+        else {
+            panic!("jerma wasn't upgraded");
+        };
+        for given in salts {
+            assert_eq!(password::parse_salt(&given).unwrap(), salt);
+        }
+    }
+
+    #[test]
+    fn strangers_cant_replace_or_burn_nonces() {
+        let db = InMemory::new();
+        db.store_client("jerma".into(), Credential::new("hunter2").to_string())
+            .unwrap();
+        let address = spawn({
+            let db = db.clone();
+            |server| server.db(db).allow_plaintext(true)
+        });
+        let (nonce, kdf, salt) = challenge(address, "jerma");
+
+        // someone else asks for plenty of nonces for jerma and guesses at them
+        for _ in 0..40 {
+            let (theirs, _, _) = challenge(address, "jerma");
+            let bogus = format!(
+                "lung/a0.1 hash auth\nclient: jerma\nnonce: {theirs}x\nhash: {}\n\n",
+                "0".repeat(64)
+            );
+            assert_eq!(
+                exchange(address, bogus.as_bytes()).status,
+                StatusCode::HashInvalid
+            );
+        }
+
+        let proof = password::prove("hunter2", kdf, &salt, &nonce);
+        let auth = format!("lung/a0.1 hash auth\nclient: jerma\nnonce: {nonce}\nhash: {proof}\n\n");
+        assert_eq!(
+            exchange(address, auth.as_bytes()).status,
+            StatusCode::HashAccepted
+        );
+    }
+
+    #[test]
+    fn nonces_are_only_good_for_their_client() {
+        let identity = Identity::generate();
+        let until = unix_now() + 60;
+        let nonce = issue_nonce(&identity, "jerma", until);
+        assert_ne!(nonce, issue_nonce(&identity, "jerma", until));
+        assert_eq!(nonce_until(&identity, "jerma", &nonce), Some(until));
+        assert_eq!(nonce_until(&identity, "bobby", &nonce), None);
+        assert_eq!(nonce_until(&Identity::generate(), "jerma", &nonce), None);
+        assert_eq!(nonce_until(&identity, "jerma", "made up"), None);
+
+        // pushing the time out breaks the mac
+        let mut raw = STANDARD.decode(&nonce).unwrap();
+        raw[..8].copy_from_slice(&(until + 3600).to_be_bytes());
+        assert_eq!(nonce_until(&identity, "jerma", &STANDARD.encode(raw)), None);
+
+        let used = UsedNonces::default();
+        assert!(used.use_up(&nonce, until).unwrap());
+        assert!(!used.use_up(&nonce, until).unwrap());
+        // ones that ran out are forgotten
+        assert!(used.use_up("old", unix_now() - 1).unwrap());
+        used.use_up("other", until).unwrap();
+        assert_eq!(lock(&used.used).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn unknown_clients_get_a_salt_too() {
+        let address = spawn(|server| server.allow_plaintext(true));
+        let (nonce, kdf, salt) = challenge(address, "nobody");
+        assert_eq!(kdf, KdfVersion::CURRENT);
+        assert_eq!(
+            nonce.len(),
+            issue_nonce(&Identity::generate(), "jerma", 0).len()
+        );
+        assert_eq!(challenge(address, "nobody").2, salt);
+        assert_ne!(challenge(address, "somebody").2, salt);
+
+        // asking for made up clients doesn't fill anything up
+        let worker = Server::new("127.0.0.1:0").unwrap().worker;
+        for i in 0..100 {
+            let ask = Request::new(RequestKind::Nonce)
+                .header(HeaderKind::Client, format!("bot{i}"))
+                .unwrap();
+            let response = handle_nonce(ask, &worker).unwrap();
+            assert_eq!(response.status, StatusCode::NonceGiven);
+        }
+        assert!(lock(&worker.nonces.used).unwrap().is_empty());
+    }
 }
@@ -0,0 +1,187 @@
+//! the body of an offline messages response. every message is framed like a tiny
+//! response of its own, a head with timestamp, from and length, an empty line, then
+//! exactly `length` bytes and a line break:
+//! ```text
+//! timestamp: 1731515023
+//! from: bobby#s1
+//! length: 2
+//!
+//! yo
+//! ```
+//! since the body is only ever read by its length, whatever it contains (headers
+//! included) can't be mistaken for the next message
+
+use std::str::FromStr;
+
+use crate::shared::{
+    ContractError, ParseError, Response, ResponseHeaderKind, ResponseKind, StatusCode,
+    crypt::Timestamp, wire,
+};
+
+/// a message waiting in a user's queue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedMessage {
+    pub timestamp: Timestamp,
+    /// user#server, or a special sender like !sealed
+    pub from: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageBatch(pub Vec<QueuedMessage>);
+
+impl MessageBatch {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for message in &self.0 {
+            out.extend_from_slice(
+                format!(
+                    "{}: {}\n{}: {}\n{}: {}\n\n",
+                    ResponseHeaderKind::Timestamp,
+                    message.timestamp,
+                    ResponseHeaderKind::From,
+                    message.from,
+                    ResponseHeaderKind::Length,
+                    message.body.len()
+                )
+                .as_bytes(),
+            );
+            out.extend_from_slice(&message.body);
+            out.push(b'\n');
+        }
+        out
+    }
+
+    /// decodes exactly `count` messages, anything left over is an error
+    pub fn decode(mut bytes: &[u8], count: usize) -> Result<Self, ParseError> {
+        let mut messages = Vec::new();
+        for i in 0..count {
+            let (head_end, body_start) = wire::find_head_end(bytes).ok_or_else(|| {
+                ParseError::InvalidBody(format!("message {i} of {count} is missing"))
+            })?;
+
+            let mut timestamp = None;
+            let mut from = None;
+            let mut length = None;
+            for line in wire::head_str(&bytes[..head_end])?.lines() {
+                let (key, value) = wire::header_line(line)?;
+                match ResponseHeaderKind::from_str(key)? {
+                    ResponseHeaderKind::Timestamp => {
+                        timestamp = Some(value.parse::<Timestamp>().map_err(|_| {
+                            ParseError::InvalidHeaderValue(format!("bad timestamp {value}"))
+                        })?)
+                    }
+                    ResponseHeaderKind::From => from = Some(value.to_string()),
+                    ResponseHeaderKind::Length => length = Some(wire::parse_length(value)?),
+                    other => {
+                        return Err(ParseError::UnexpectedHeader(format!(
+                            "queued messages don't take a {other} header"
+                        )));
+                    }
+                }
+            }
+            let missing = |name: &str| ParseError::HeaderMissing(format!("message {i}: {name}"));
+            let timestamp = timestamp.ok_or_else(|| missing("timestamp"))?;
+            let from = from.ok_or_else(|| missing("from"))?;
+            let length = length.ok_or_else(|| missing("length"))?;
+
+            let rest = &bytes[body_start..];
+            if rest.get(length) != Some(&b'\n') {
+                return Err(ParseError::BodyLengthMismatch(format!(
+                    "message {i} says {length} bytes"
+                )));
+            }
+            messages.push(QueuedMessage {
+                timestamp,
+                from,
+                body: rest[..length].to_vec(),
+            });
+            bytes = &rest[length + 1..];
+        }
+        if !bytes.is_empty() {
+            return Err(ParseError::InvalidBody(format!(
+                "{} bytes after the last of {count} messages",
+                bytes.len()
+            )));
+        }
+        Ok(Self(messages))
+    }
+
+    /// an offline messages response carrying this batch
+    pub fn to_response(&self) -> Result<Response, ContractError> {
+        Response::build(ResponseKind::OfflineMessages)
+            .header(ResponseHeaderKind::Count, self.0.len().to_string())
+            .body(self.encode())
+            .finish()
+    }
+}
+
+impl TryFrom<&Response> for MessageBatch {
+    type Error = ParseError;
+
+    fn try_from(response: &Response) -> Result<Self, ParseError> {
+        if response.status != StatusCode::OfflineMessages {
+            return Err(ParseError::InvalidFormat(format!(
+                "expected offline messages, got {}",
+                response.status
+            )));
+        }
+        let count = response
+            .headers
+            .get(&ResponseHeaderKind::Count)
+            .ok_or_else(|| ParseError::HeaderMissing("count".into()))?;
+        let count = count
+            .parse::<usize>()
+            .map_err(|_| ParseError::InvalidHeaderValue(format!("bad count {count}")))?;
+        Self::decode(response.body.as_deref().unwrap_or_default(), count)
+    }
+}
+
+// ===== tests =====
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(from: &str, body: &[u8]) -> QueuedMessage {
+        QueuedMessage {
+            timestamp: 1731515023,
+            from: from.into(),
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn batch_roundtrips_through_a_response() {
+        let batch = MessageBatch(vec![
+            message("bobby#s1", b"yo"),
+            message("!plain_sealed", b""),
+            message("!sealed", &[0, 0xff, b'\n']),
+        ]);
+        let bytes = batch.to_response().unwrap().to_bytes();
+        let response = Response::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(MessageBatch::try_from(&response).unwrap(), batch);
+    }
+
+    #[test]
+    fn fake_headers_in_a_body_stay_in_the_body() {
+        let rekt =
+            b"aasdfasdfasd f\n\n\n\n\ntimestamp: 1\nfrom: bobby#example\nlength: 123123\nrekt";
+        let batch = MessageBatch(vec![message("bobby#s1", rekt), message("bobby#s1", b"yo")]);
+        let decoded = MessageBatch::decode(&batch.encode(), 2).unwrap();
+        assert_eq!(decoded, batch);
+    }
+
+    #[test]
+    fn count_must_match() {
+        let batch = MessageBatch(vec![message("bobby#s1", b"yo")]);
+        assert!(MessageBatch::decode(&batch.encode(), 2).is_err());
+        assert!(MessageBatch::decode(&batch.encode(), 0).is_err());
+    }
+
+    #[test]
+    fn empty_batch() {
+        let response = MessageBatch::default().to_response().unwrap();
+        let response = Response::try_from(response.to_bytes().as_slice()).unwrap();
+        assert!(MessageBatch::try_from(&response).unwrap().0.is_empty());
+    }
+}
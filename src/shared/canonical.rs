@@ -0,0 +1,144 @@
+//! byte-exact form of signed records, so two implementations agree on what was signed.
+//! a record is a label plus named fields, encoded as
+//! ```text
+//! lung-canonical/1
+//! label:13:friend-record
+//! addrs:12:1.2.3.4:1337
+//! seq:2:17
+//! server:2:s1
+//! ```
+//! fields are sorted by name (bytewise), every value is prefixed with its length in
+//! bytes and followed by a line break. values are never escaped, the length prefix is
+//! what delimits them, so they can hold anything including line breaks and colons.
+//! names are lowercase ascii letters, digits and dashes.
+//!
+//! signatures are `SIGN(priv, SHA256(canonical bytes))`
+
+use std::collections::BTreeMap;
+
+use ed25519_compact::{PublicKey, SecretKey, Signature};
+use sha2::{Digest, Sha256};
+
+use crate::shared::crypt::signing::{sign_message, verify_signature};
+
+const MAGIC: &[u8] = b"lung-canonical/1\n";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanonicalRecord {
+    label: &'static str,
+    fields: BTreeMap<&'static str, Vec<u8>>,
+}
+
+impl CanonicalRecord {
+    /// a record of a kind, like "friend-record" or "revoke"
+    pub fn new(label: &'static str) -> Self {
+        assert!(valid_name(label), "invalid canonical label {label:?}");
+        Self {
+            label,
+            fields: BTreeMap::new(),
+        }
+    }
+
+    /// adds a field. names are protocol constants, setting one twice keeps the last value
+    pub fn field(mut self, name: &'static str, value: impl AsRef<[u8]>) -> Self {
+        assert!(
+            valid_name(name) && name != "label",
+            "invalid canonical field name {name:?}"
+        );
+        self.fields.insert(name, value.as_ref().to_vec());
+        self
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        push_field(&mut out, "label", self.label.as_bytes());
+        for (name, value) in &self.fields {
+            push_field(&mut out, name, value);
+        }
+        out
+    }
+
+    pub fn digest(&self) -> [u8; 32] {
+        Sha256::digest(self.to_bytes()).into()
+    }
+
+    pub fn sign(&self, sk: &SecretKey) -> Signature {
+        sign_message(sk, &self.digest())
+    }
+
+    pub fn verify(&self, pk: &PublicKey, sig: &Signature) -> bool {
+        verify_signature(pk, &self.digest(), sig)
+    }
+}
+
+fn push_field(out: &mut Vec<u8>, name: &str, value: &[u8]) {
+    out.extend_from_slice(format!("{name}:{}:", value.len()).as_bytes());
+    out.extend_from_slice(value);
+    out.push(b'\n');
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+// ===== tests =====
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::crypt::signing::gen_sign_keys;
+
+    #[test]
+    fn encoding_is_byte_exact() {
+        let record = CanonicalRecord::new("revoke")
+            .field("server", "s2")
+            .field("seq", "18");
+        assert_eq!(
+            record.to_bytes(),
+            b"lung-canonical/1\nlabel:6:revoke\nseq:2:18\nserver:2:s2\n"
+        );
+    }
+
+    #[test]
+    fn field_order_does_not_matter() {
+        let a = CanonicalRecord::new("friend-record")
+            .field("server", "s1")
+            .field("seq", "17");
+        let b = CanonicalRecord::new("friend-record")
+            .field("seq", "17")
+            .field("server", "s1");
+        assert_eq!(a.to_bytes(), b.to_bytes());
+    }
+
+    #[test]
+    fn values_cannot_forge_fields() {
+        let smuggled = CanonicalRecord::new("revoke").field("server", "s2\nseq:2:18");
+        let honest = CanonicalRecord::new("revoke")
+            .field("server", "s2")
+            .field("seq", "18");
+        assert_ne!(smuggled.to_bytes(), honest.to_bytes());
+        assert_ne!(
+            CanonicalRecord::new("revoke").to_bytes(),
+            CanonicalRecord::new("deliver").to_bytes()
+        );
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let (sk, pk) = gen_sign_keys();
+        let record = CanonicalRecord::new("deliver").field("message-id", "abc");
+        let sig = record.sign(&sk);
+        assert!(record.verify(&pk, &sig));
+
+        let tampered = record.clone().field("message-id", "abd");
+        assert!(!tampered.verify(&pk, &sig));
+    }
+
+    #[test]
+    #[should_panic]
+    fn names_are_restricted() {
+        let _ = CanonicalRecord::new("revoke").field("Server", "s2");
+    }
+}
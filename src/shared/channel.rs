@@ -0,0 +1,173 @@
+//! an encrypted channel for connections that stay open, so every request doesn't pay
+//! for a key exchange like an envelope does
+//! ```text
+//! -> lung/a0.1 handshake
+//!    pubkey: BASE64(client ephemeral x25519)
+//! <- lung/a0.1 status 51: handshake accepted
+//!    pubkey: BASE64(server ephemeral x25519)
+//! ```
+//! from then on requests go as `frame` requests and come back as `status 2: encrypted`,
+//! each body being the next frame of [SecureChannel]. a frame that doesn't decrypt ends
+//! the connection, there's no telling what the counters should be after that
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::shared::{
+    HeaderKind, ParseError, Request, RequestKind, Response, ResponseHeaderKind, ResponseKind,
+    StatusCode,
+    crypt::traffic::{SecureChannel, gen_keys},
+};
+
+/// a handshake the client sent and is waiting on the answer to
+pub struct ClientHandshake {
+    eph_secret: StaticSecret,
+    server_pub: PublicKey,
+}
+
+/// starts a channel with the server holding `server_pub`
+pub fn handshake(server_pub: &PublicKey) -> (Request, ClientHandshake) {
+    let (eph_secret, eph_pub) = gen_keys();
+    let request = Request::new(RequestKind::Handshake)
+        .header(HeaderKind::Pubkey, encode_key(&eph_pub))
+        .expect("base64 is a valid header value");
+    let pending = ClientHandshake {
+        eph_secret,
+        server_pub: *server_pub,
+    };
+    (request, pending)
+}
+
+impl ClientHandshake {
+    /// the client's end of the channel, once the server has answered
+    pub fn finish(self, response: &Response) -> Result<SecureChannel, ParseError> {
+        if response.status != StatusCode::HandshakeAccepted {
+            return Err(ParseError::InvalidBody(format!(
+                "handshake refused: {}",
+                response.status
+            )));
+        }
+        let server_eph = decode_key(response.headers.get(&ResponseHeaderKind::Pubkey))?;
+        Ok(SecureChannel::client(
+            &self.eph_secret,
+            &self.server_pub,
+            &server_eph,
+        ))
+    }
+}
+
+/// the server's end of the channel a handshake asks for, and the answer to send back
+pub fn accept(
+    handshake: &Request,
+    server_secret: &StaticSecret,
+) -> Result<(SecureChannel, Response), ParseError> {
+    let client_eph = decode_key(handshake.headers.get(&HeaderKind::Pubkey))?;
+    let (eph_secret, eph_pub) = gen_keys();
+    let response = Response::build(ResponseKind::HandshakeAccepted)
+        .header(ResponseHeaderKind::Pubkey, encode_key(&eph_pub))
+        .finish()?;
+    let channel = SecureChannel::server(server_secret, &eph_secret, &client_eph);
+    Ok((channel, response))
+}
+
+/// puts `request` in the next frame
+pub fn frame(channel: &mut SecureChannel, request: &Request) -> Result<Request, ParseError> {
+    let body = channel.seal(&request.to_bytes()).ok_or_else(exhausted)?;
+    Ok(Request::new(RequestKind::Frame).body(body))
+}
+
+/// the inner request's bytes, still to be parsed
+pub fn open_frame(channel: &mut SecureChannel, frame: &Request) -> Result<Vec<u8>, ParseError> {
+    channel
+        .open(frame.body.as_deref().unwrap_or_default())
+        .map_err(|_| ParseError::InvalidBody("frame doesn't decrypt".into()))
+}
+
+/// puts `response` in the next frame back
+pub fn frame_reply(
+    channel: &mut SecureChannel,
+    response: &Response,
+) -> Result<Response, ParseError> {
+    let body = channel.seal(&response.to_bytes()).ok_or_else(exhausted)?;
+    Ok(Response::build(ResponseKind::Encrypted)
+        .version(response.version)
+        .body(body)
+        .finish()?)
+}
+
+/// the response inside a framed reply
+pub fn open_reply(channel: &mut SecureChannel, reply: &Response) -> Result<Response, ParseError> {
+    if reply.status != StatusCode::Encrypted {
+        return Err(ParseError::InvalidBody(format!(
+            "expected an encrypted reply, got {}",
+            reply.status
+        )));
+    }
+    let plaintext = channel
+        .open(reply.body.as_deref().unwrap_or_default())
+        .map_err(|_| ParseError::InvalidBody("reply doesn't decrypt".into()))?;
+    Response::try_from(plaintext.as_slice())
+}
+
+fn exhausted() -> ParseError {
+    ParseError::TooLarge("the channel ran out of nonces, handshake again".into())
+}
+
+fn encode_key(key: &PublicKey) -> String {
+    STANDARD.encode(key.as_bytes())
+}
+
+fn decode_key(value: Option<&String>) -> Result<PublicKey, ParseError> {
+    let value = value.ok_or_else(|| ParseError::HeaderMissing("pubkey".into()))?;
+    let bytes: [u8; 32] = STANDARD
+        .decode(value)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            ParseError::InvalidHeaderValue("pubkey must be a base64 x25519 key".into())
+        })?;
+    Ok(PublicKey::from(bytes))
+}
+
+// ===== tests =====
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake_and_frames() {
+        let (server_secret, server_pub) = gen_keys();
+        let (hello, pending) = handshake(&server_pub);
+        let hello = Request::try_from(hello.to_bytes().as_slice()).unwrap();
+
+        let (mut server, accepted) = accept(&hello, &server_secret).unwrap();
+        let accepted = Response::try_from(accepted.to_bytes().as_slice()).unwrap();
+        let mut client = pending.finish(&accepted).unwrap();
+
+        for _ in 0..2 {
+            let request = Request::new(RequestKind::Info);
+            let sent = frame(&mut client, &request).unwrap();
+            let inner = open_frame(&mut server, &sent).unwrap();
+            assert_eq!(Request::try_from(inner.as_slice()).unwrap(), request);
+
+            let response = Response::build(ResponseKind::Teapot).finish().unwrap();
+            let reply = frame_reply(&mut server, &response).unwrap();
+            let opened = open_reply(&mut client, &reply).unwrap();
+            assert_eq!(opened.status, StatusCode::Teapot);
+        }
+    }
+
+    #[test]
+    fn bad_handshake_keys_are_refused() {
+        let (server_secret, _) = gen_keys();
+        for key in ["AAAA", "not base64!"] {
+            let hello = Request::new(RequestKind::Handshake)
+                .header(HeaderKind::Pubkey, key)
+                .unwrap();
+            assert!(matches!(
+                accept(&hello, &server_secret),
+                Err(ParseError::InvalidHeaderValue(_))
+            ));
+        }
+    }
+}
@@ -94,7 +94,7 @@ pub mod traffic {
 
     /// generates an ed25519 keypair
     pub fn gen_keys() -> (StaticSecret, PublicKey) {
-        let secret = StaticSecret::random_from_rng(&mut OsRng);
+        let secret = StaticSecret::random_from_rng(OsRng);
         let public = PublicKey::from(&secret);
         (secret, public)
     }
@@ -108,15 +108,34 @@ pub mod traffic {
         okm
     }
 
+    /// key for answering a [client_encrypt]ed message, both sides derive it from the same
+    /// dh secret under a different label
+    pub fn reply_key_from_shared(shared: &[u8]) -> [u8; 32] {
+        let hk = Hkdf::<Sha256>::new(None, shared);
+        let mut okm = [0u8; 32];
+        hk.expand(b"lung/a0.1 reply", &mut okm)
+            .expect("this should never panic");
+        okm
+    }
+
     /// for the client to encrypt plaintext w/ a server's static pubkey
     /// out: [ephemeral_pub (32 bytes)] || [nonce (12 bytes)] || [ciphertext...]
     pub fn client_encrypt(server_pub: &PublicKey, plaintext: &[u8]) -> Vec<u8> {
-        let eph_secret = StaticSecret::random_from_rng(&mut OsRng);
+        client_encrypt_with_reply(server_pub, plaintext).0
+    }
+
+    /// [client_encrypt], plus the key the server's reply will be encrypted with
+    pub fn client_encrypt_with_reply(
+        server_pub: &PublicKey,
+        plaintext: &[u8],
+    ) -> (Vec<u8>, [u8; 32]) {
+        let eph_secret = StaticSecret::random_from_rng(OsRng);
         let eph_pub = PublicKey::from(&eph_secret);
 
         let shared = eph_secret.diffie_hellman(server_pub);
 
         let aead_key_bytes = hkdf_from_shared(shared.as_bytes());
+        let reply_key = reply_key_from_shared(shared.as_bytes());
 
         // aead encrypt w/ 12 bit nonce chacha20poly1305
         let cipher = ChaCha20Poly1305::new(&aead_key_bytes.into());
@@ -133,13 +152,21 @@ pub mod traffic {
         out.extend(eph_pub.as_bytes());
         out.extend(&nonce_bytes);
         out.append(&mut ciphertext);
-        out
+        (out, reply_key)
     }
 
     pub fn server_decrypt(
         server_secret: &StaticSecret,
         ciphertext: &[u8],
     ) -> Result<Vec<u8>, DecryptError> {
+        server_decrypt_with_reply(server_secret, ciphertext).map(|(plaintext, _)| plaintext)
+    }
+
+    /// [server_decrypt], plus the key to encrypt the reply with
+    pub fn server_decrypt_with_reply(
+        server_secret: &StaticSecret,
+        ciphertext: &[u8],
+    ) -> Result<(Vec<u8>, [u8; 32]), DecryptError> {
         if ciphertext.len() < 32 + 12 {
             return Err(DecryptError::CiphertextTooShort);
         };
@@ -158,9 +185,26 @@ pub mod traffic {
         let cipher = ChaCha20Poly1305::new(&key_bytes.into());
         let nonce = nonce_bytes.into();
 
-        cipher
+        let plaintext = cipher
             .decrypt(nonce, ct)
-            .map_err(|_| DecryptError::DecryptionFailed)
+            .map_err(|_| DecryptError::DecryptionFailed)?;
+        Ok((plaintext, reply_key_from_shared(shared.as_bytes())))
+    }
+
+    /// out: [nonce (12 bytes)] || [ciphertext...], the format [decrypt_aead] reads
+    pub fn encrypt_aead(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+        let cipher = ChaCha20Poly1305::new(key.into());
+        let mut nonce_bytes = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce_bytes);
+        let nonce: &Nonce<typenum::U12> = &nonce_bytes.into();
+        let mut ciphertext = cipher
+            .encrypt(nonce, plaintext)
+            .expect("something went very wrong if this failed");
+
+        let mut out = Vec::with_capacity(12 + ciphertext.len());
+        out.extend(&nonce_bytes);
+        out.append(&mut ciphertext);
+        out
     }
 
     pub fn decrypt_aead(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, &'static str> {
@@ -172,6 +216,111 @@ pub mod traffic {
         let nonce = nonce_bytes.into();
         cipher.decrypt(nonce, ct).map_err(|_| "decryption failed")
     }
+
+    /// a two-way encrypted channel for a connection that stays open. each direction has
+    /// its own key and its own counter, the counter is the nonce. it's never sent, so a
+    /// frame that's replayed, dropped or out of order doesn't decrypt
+    pub struct SecureChannel {
+        send: ChaCha20Poly1305,
+        recv: ChaCha20Poly1305,
+        sent: u64,
+        received: u64,
+    }
+
+    impl SecureChannel {
+        /// the client's end. `eph_secret` is the key it sent in the handshake, `server_pub`
+        /// the certificate key and `server_eph` the key the server answered with
+        pub fn client(
+            eph_secret: &StaticSecret,
+            server_pub: &PublicKey,
+            server_eph: &PublicKey,
+        ) -> Self {
+            let (c2s, s2c) = channel_keys(
+                eph_secret.diffie_hellman(server_pub).as_bytes(),
+                eph_secret.diffie_hellman(server_eph).as_bytes(),
+                &PublicKey::from(eph_secret),
+                server_eph,
+            );
+            Self::new(c2s, s2c)
+        }
+
+        /// the server's end, `eph_secret` is the key it answers the handshake with
+        pub fn server(
+            server_secret: &StaticSecret,
+            eph_secret: &StaticSecret,
+            client_eph: &PublicKey,
+        ) -> Self {
+            let (c2s, s2c) = channel_keys(
+                server_secret.diffie_hellman(client_eph).as_bytes(),
+                eph_secret.diffie_hellman(client_eph).as_bytes(),
+                client_eph,
+                &PublicKey::from(eph_secret),
+            );
+            Self::new(s2c, c2s)
+        }
+
+        fn new(send: [u8; 32], recv: [u8; 32]) -> Self {
+            Self {
+                send: ChaCha20Poly1305::new(&send.into()),
+                recv: ChaCha20Poly1305::new(&recv.into()),
+                sent: 0,
+                received: 0,
+            }
+        }
+
+        /// encrypts the next outgoing frame. None once the counter has run out, the
+        /// channel has to be renegotiated then
+        pub fn seal(&mut self, plaintext: &[u8]) -> Option<Vec<u8>> {
+            let nonce = counter_nonce(self.sent)?;
+            self.sent += 1;
+            Some(
+                self.send
+                    .encrypt(&nonce.into(), plaintext)
+                    .expect("something went very wrong if this failed"),
+            )
+        }
+
+        /// decrypts the next incoming frame. the counter only moves on success
+        pub fn open(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, DecryptError> {
+            let nonce = counter_nonce(self.received).ok_or(DecryptError::DecryptionFailed)?;
+            let plaintext = self
+                .recv
+                .decrypt(&nonce.into(), ciphertext)
+                .map_err(|_| DecryptError::DecryptionFailed)?;
+            self.received += 1;
+            Ok(plaintext)
+        }
+    }
+
+    /// (client to server, server to client) keys. both dh secrets go in, the one with the
+    /// certificate key proves it's the pinned server, the ephemeral one keeps old
+    /// traffic safe if that key leaks later
+    fn channel_keys(
+        dh_static: &[u8; 32],
+        dh_eph: &[u8; 32],
+        client_eph: &PublicKey,
+        server_eph: &PublicKey,
+    ) -> ([u8; 32], [u8; 32]) {
+        let ikm = [dh_static.as_slice(), dh_eph.as_slice()].concat();
+        let salt = [client_eph.as_bytes().as_slice(), server_eph.as_bytes()].concat();
+        let hk = Hkdf::<Sha256>::new(Some(&salt), &ikm);
+        let (mut c2s, mut s2c) = ([0u8; 32], [0u8; 32]);
+        hk.expand(b"lung/a0.1 c2s", &mut c2s)
+            .expect("this should never panic");
+        hk.expand(b"lung/a0.1 s2c", &mut s2c)
+            .expect("this should never panic");
+        (c2s, s2c)
+    }
+
+    /// 4 zero bytes, then the counter big endian. the last value is never used
+    fn counter_nonce(counter: u64) -> Option<[u8; 12]> {
+        if counter == u64::MAX {
+            return None;
+        }
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&counter.to_be_bytes());
+        Some(nonce)
+    }
     // ===== tests =====
     #[cfg(test)]
     mod tests {
@@ -201,6 +350,72 @@ pub mod traffic {
             assert_eq!(plaintext, message);
         }
 
+        #[test]
+        fn both_sides_agree_on_the_reply_key() {
+            let (server_secret, server_pub) = gen_keys();
+            let (ciphertext, client_key) = client_encrypt_with_reply(&server_pub, b"ping");
+            let (plaintext, server_key) =
+                server_decrypt_with_reply(&server_secret, &ciphertext).unwrap();
+            assert_eq!(plaintext, b"ping");
+            assert_eq!(client_key, server_key);
+
+            let reply = encrypt_aead(&server_key, b"pong");
+            assert_eq!(decrypt_aead(&client_key, &reply).unwrap(), b"pong");
+        }
+
+        fn channel_pair() -> (SecureChannel, SecureChannel) {
+            let (server_secret, server_pub) = gen_keys();
+            let (client_eph, client_eph_pub) = gen_keys();
+            let (server_eph, server_eph_pub) = gen_keys();
+            (
+                SecureChannel::client(&client_eph, &server_pub, &server_eph_pub),
+                SecureChannel::server(&server_secret, &server_eph, &client_eph_pub),
+            )
+        }
+
+        #[test]
+        fn channel_works_both_ways() {
+            let (mut client, mut server) = channel_pair();
+            for i in 0..3u8 {
+                let frame = client.seal(&[i]).unwrap();
+                assert_eq!(server.open(&frame).unwrap(), [i]);
+                let reply = server.seal(&[i, i]).unwrap();
+                assert_eq!(client.open(&reply).unwrap(), [i, i]);
+            }
+        }
+
+        #[test]
+        fn channel_directions_use_different_keys() {
+            let (mut client, _) = channel_pair();
+            let frame = client.seal(b"echo").unwrap();
+            // a frame reflected back at its sender doesn't open
+            assert!(client.open(&frame).is_err());
+        }
+
+        #[test]
+        fn channel_rejects_replays_and_reordering() {
+            let (mut client, mut server) = channel_pair();
+            let first = client.seal(b"one").unwrap();
+            let second = client.seal(b"two").unwrap();
+
+            assert!(server.open(&second).is_err());
+            assert_eq!(server.open(&first).unwrap(), b"one");
+            assert!(server.open(&first).is_err());
+            assert_eq!(server.open(&second).unwrap(), b"two");
+        }
+
+        #[test]
+        fn channel_needs_the_pinned_server_key() {
+            let (_, server_pub) = gen_keys();
+            let (impostor_secret, _) = gen_keys();
+            let (client_eph, client_eph_pub) = gen_keys();
+            let (server_eph, server_eph_pub) = gen_keys();
+            let mut client = SecureChannel::client(&client_eph, &server_pub, &server_eph_pub);
+            let mut impostor =
+                SecureChannel::server(&impostor_secret, &server_eph, &client_eph_pub);
+            assert!(impostor.open(&client.seal(b"hi").unwrap()).is_err());
+        }
+
         #[test]
         fn server_decrypt_fails_with_wrong_key() {
             let (_, server_pub) = gen_keys();
@@ -306,6 +521,14 @@ pub mod token {
             Token { id, until }
         }
 
+        pub fn id(&self) -> &str {
+            &self.id
+        }
+
+        pub fn until(&self) -> Timestamp {
+            self.until
+        }
+
         pub fn encrypt(self, key_bytes: &[u8; 32]) -> Vec<u8> {
             let key = key_bytes.into();
             let cipher = ChaCha20Poly1305::new(key);
@@ -355,3 +578,278 @@ pub mod token {
         }
     }
 }
+
+// ===== passwords =====
+/// how passwords are stored and proven. the password never crosses the wire, and
+/// neither does anything the server keeps
+/// ```text
+/// input  = hex(SHA256(password))
+/// salted = argon2id(input, salt), parameters by kdf version
+/// stored = SHA256(salted)                      kept by the server with the salt
+/// proof  = salted XOR SHA256(stored || nonce)  sent in hash auth, as hex
+/// ```
+/// the server takes the xor back off with `stored` and checks that what's left hashes
+/// to it. a leaked database only has `stored`, guessing passwords from it means going
+/// through argon2 for every guess. `input` being the old SHA-256 digest is what lets
+/// those entries be upgraded without knowing the password
+pub mod password {
+    use std::{fmt, str::FromStr};
+
+    use argon2::{Algorithm, Argon2, Params, Version};
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use sha2::{Digest, Sha256};
+    use subtle::ConstantTimeEq;
+
+    pub const SALT_LEN: usize = 16;
+
+    /// argon2 parameters, named by the version stored with each credential. versions
+    /// stay once they've been used, entries made with them still have to verify
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum KdfVersion {
+        /// argon2id, 19 MiB, 2 passes, 1 lane
+        V1,
+    }
+
+    impl KdfVersion {
+        /// what new credentials are made with
+        pub const CURRENT: KdfVersion = KdfVersion::V1;
+
+        fn argon2(self) -> Argon2<'static> {
+            let params = match self {
+                KdfVersion::V1 => Params::new(19 * 1024, 2, 1, Some(32)),
+            };
+            Argon2::new(
+                Algorithm::Argon2id,
+                Version::V0x13,
+                params.expect("kdf parameters are valid"),
+            )
+        }
+    }
+
+    impl fmt::Display for KdfVersion {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                KdfVersion::V1 => write!(f, "v1"),
+            }
+        }
+    }
+
+    impl FromStr for KdfVersion {
+        type Err = CredentialError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "v1" => Ok(KdfVersion::V1),
+                _ => Err(CredentialError::UnknownKdf),
+            }
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum CredentialError {
+        UnknownKdf,
+        InvalidFormat,
+    }
+
+    /// what the server keeps for a client. written out it's either the legacy digest,
+    /// or `[kdf]$BASE64(salt)$BASE64(stored)`
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Credential {
+        /// a bare hex SHA256(password), from before passwords went through a kdf.
+        /// nothing can be proven against it, [Credential::upgrade] it first
+        Legacy(String),
+        Salted {
+            kdf: KdfVersion,
+            salt: [u8; SALT_LEN],
+            stored: [u8; 32],
+        },
+    }
+
+    impl Credential {
+        /// a credential for `password` with a fresh salt
+        pub fn new(password: &str) -> Self {
+            Self::from_input(&input(password))
+        }
+
+        fn from_input(input: &str) -> Self {
+            let kdf = KdfVersion::CURRENT;
+            let salt = rand::random();
+            let stored = Sha256::digest(derive(kdf, input, &salt)).into();
+            Credential::Salted { kdf, salt, stored }
+        }
+
+        /// runs a legacy digest through the kdf, which doesn't need the password.
+        /// salted credentials come back as they are
+        pub fn upgrade(self) -> Self {
+            match self {
+                Credential::Legacy(digest) => Self::from_input(&digest),
+                salted => salted,
+            }
+        }
+
+        /// whether `proof` was made from the right password for `nonce`. the
+        /// comparison takes as long whatever the proof is
+        pub fn verify(&self, nonce: &str, proof: &str) -> bool {
+            let Credential::Salted { stored, .. } = self else {
+                return false;
+            };
+            let Some(proof) = decode_hex(proof) else {
+                return false;
+            };
+            let salted = xor(&proof, &mask(stored, nonce));
+            let hashed: [u8; 32] = Sha256::digest(salted).into();
+            hashed[..].ct_eq(&stored[..]).into()
+        }
+    }
+
+    impl fmt::Display for Credential {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Credential::Legacy(digest) => write!(f, "{digest}"),
+                Credential::Salted { kdf, salt, stored } => write!(
+                    f,
+                    "{kdf}${}${}",
+                    STANDARD.encode(salt),
+                    STANDARD.encode(stored)
+                ),
+            }
+        }
+    }
+
+    impl FromStr for Credential {
+        type Err = CredentialError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let Some((kdf, rest)) = s.split_once('$') else {
+                return match decode_hex(s) {
+                    Some(_) => Ok(Credential::Legacy(s.to_ascii_lowercase())),
+                    None => Err(CredentialError::InvalidFormat),
+                };
+            };
+            let kdf = kdf.parse()?;
+            let (salt, stored) = rest.split_once('$').ok_or(CredentialError::InvalidFormat)?;
+            Ok(Credential::Salted {
+                kdf,
+                salt: decode_array(salt)?,
+                stored: decode_array(stored)?,
+            })
+        }
+    }
+
+    /// the salt as the nonce response carries it
+    pub fn parse_salt(value: &str) -> Result<[u8; SALT_LEN], CredentialError> {
+        decode_array(value)
+    }
+
+    /// what the client sends as `hash` in hash auth, with the kdf and salt the nonce
+    /// came with
+    pub fn prove(password: &str, kdf: KdfVersion, salt: &[u8; SALT_LEN], nonce: &str) -> String {
+        let salted = derive(kdf, &input(password), salt);
+        let stored: [u8; 32] = Sha256::digest(salted).into();
+        xor(&salted, &mask(&stored, nonce))
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    /// hex(SHA256(password)), the same digest legacy entries hold
+    fn input(password: &str) -> String {
+        format!("{:x}", Sha256::digest(password))
+    }
+
+    fn derive(kdf: KdfVersion, input: &str, salt: &[u8; SALT_LEN]) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        kdf.argon2()
+            .hash_password_into(input.as_bytes(), salt, &mut out)
+            .expect("the salt and output fit the kdf parameters");
+        out
+    }
+
+    fn mask(stored: &[u8; 32], nonce: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(stored);
+        hasher.update(nonce);
+        hasher.finalize().into()
+    }
+
+    fn xor(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+        std::array::from_fn(|i| a[i] ^ b[i])
+    }
+
+    fn decode_hex(s: &str) -> Option<[u8; 32]> {
+        if s.len() != 64 || !s.is_ascii() {
+            return None;
+        }
+        let mut out = [0u8; 32];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(out)
+    }
+
+    fn decode_array<const N: usize>(s: &str) -> Result<[u8; N], CredentialError> {
+        STANDARD
+            .decode(s)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(CredentialError::InvalidFormat)
+    }
+
+    // ===== tests =====
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn salt_of(credential: &Credential) -> [u8; SALT_LEN] {
+            match credential {
+                Credential::Salted { salt, .. } => *salt,
+                Credential::Legacy(_) => panic!("not salted"),
+            }
+        }
+
+        #[test]
+        fn right_password_proves_wrong_one_doesnt() {
+            let credential = Credential::new("hunter2");
+            let salt = salt_of(&credential);
+            let proof = prove("hunter2", KdfVersion::CURRENT, &salt, "nonce");
+            assert!(credential.verify("nonce", &proof));
+            assert!(!credential.verify("other nonce", &proof));
+
+            let wrong = prove("hunter3", KdfVersion::CURRENT, &salt, "nonce");
+            assert!(!credential.verify("nonce", &wrong));
+            assert!(!credential.verify("nonce", "not hex"));
+        }
+
+        #[test]
+        fn legacy_entries_upgrade_without_the_password() {
+            let legacy: Credential = format!("{:X}", Sha256::digest("hunter2")).parse().unwrap();
+            assert!(matches!(legacy, Credential::Legacy(_)));
+            assert!(!legacy.verify("nonce", &"0".repeat(64)));
+
+            let upgraded = legacy.upgrade();
+            let proof = prove("hunter2", KdfVersion::CURRENT, &salt_of(&upgraded), "nonce");
+            assert!(upgraded.verify("nonce", &proof));
+        }
+
+        #[test]
+        fn written_out_and_read_back() {
+            let credential = Credential::new("hunter2");
+            let written = credential.to_string();
+            assert!(written.starts_with("v1$"));
+            assert!(!written.contains(&input("hunter2")));
+            assert_eq!(written.parse(), Ok(credential));
+
+            assert_eq!(
+                "v9$AAAA$AAAA".parse::<Credential>(),
+                Err(CredentialError::UnknownKdf)
+            );
+            for broken in ["", "hash", "v1$", "v1$AAAA$AAAA"] {
+                assert_eq!(
+                    broken.parse::<Credential>(),
+                    Err(CredentialError::InvalidFormat),
+                    "{broken:?}"
+                );
+            }
+        }
+    }
+}
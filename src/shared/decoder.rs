@@ -0,0 +1,377 @@
+//! incremental decoding for connections that stay open between messages. bytes are
+//! fed in as they arrive and requests (or, on the client, responses) come out once
+//! they're complete, whatever's left over stays buffered for the next one
+
+use crate::shared::{
+    ParseError, Request, Response,
+    wire::{self, ParseLimits},
+};
+
+/// a message whose head can be parsed before its body has arrived
+trait Framed: Sized {
+    fn parse_head(head: &[u8], limits: &ParseLimits) -> Result<Self, ParseError>;
+    fn body_length(&self, limits: &ParseLimits) -> Result<Option<usize>, ParseError>;
+    /// puts the body in, and checks whatever needs the whole message
+    fn complete(&mut self, body: Option<Vec<u8>>) -> Result<(), ParseError>;
+    fn parse(bytes: &[u8], limits: &ParseLimits) -> Result<Self, ParseError>;
+}
+
+impl Framed for Request {
+    fn parse_head(head: &[u8], limits: &ParseLimits) -> Result<Self, ParseError> {
+        Request::parse_head(head, limits)
+    }
+    fn body_length(&self, limits: &ParseLimits) -> Result<Option<usize>, ParseError> {
+        Request::body_length(self, limits)
+    }
+    fn complete(&mut self, body: Option<Vec<u8>>) -> Result<(), ParseError> {
+        self.body = body;
+        Ok(())
+    }
+    fn parse(bytes: &[u8], limits: &ParseLimits) -> Result<Self, ParseError> {
+        Request::parse_with_limits(bytes, limits)
+    }
+}
+
+impl Framed for Response {
+    fn parse_head(head: &[u8], limits: &ParseLimits) -> Result<Self, ParseError> {
+        Response::parse_head(head, limits)
+    }
+    fn body_length(&self, limits: &ParseLimits) -> Result<Option<usize>, ParseError> {
+        Response::body_length(self, limits)
+    }
+    fn complete(&mut self, body: Option<Vec<u8>>) -> Result<(), ParseError> {
+        self.body = body;
+        self.check_status()
+    }
+    fn parse(bytes: &[u8], limits: &ParseLimits) -> Result<Self, ParseError> {
+        Response::parse_with_limits(bytes, limits)
+    }
+}
+
+/// the framing both decoders share, a head up to an empty line and then `length`
+/// bytes of body
+#[derive(Debug)]
+struct Frames<T> {
+    buf: Vec<u8>,
+    limits: ParseLimits,
+    /// a message whose head has been parsed and is waiting for `length` body bytes
+    pending: Option<(T, usize)>,
+}
+
+impl<T: Framed> Frames<T> {
+    fn new(limits: ParseLimits) -> Self {
+        Self {
+            buf: Vec::new(),
+            limits,
+            pending: None,
+        }
+    }
+
+    fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.is_none() && self.buf.iter().all(|b| matches!(b, b'\r' | b'\n'))
+    }
+
+    fn next(&mut self) -> Result<Option<T>, ParseError> {
+        let result = self.advance();
+        if result.is_err() {
+            self.buf.clear();
+            self.pending = None;
+        }
+        result
+    }
+
+    fn finish(&mut self) -> Result<Option<T>, ParseError> {
+        if let Some(message) = self.next()? {
+            return Ok(Some(message));
+        }
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let rest = std::mem::take(&mut self.buf);
+        match self.pending.take() {
+            Some((_, length)) => Err(ParseError::BodyLengthMismatch(format!(
+                "length says {length} bytes, got {}",
+                rest.len()
+            ))),
+            None => T::parse(skip_blank_lines(&rest), &self.limits).map(Some),
+        }
+    }
+
+    fn advance(&mut self) -> Result<Option<T>, ParseError> {
+        if self.pending.is_none() {
+            // tolerate line breaks trailing the previous message
+            let skip = self.buf.len() - skip_blank_lines(&self.buf).len();
+            self.buf.drain(..skip);
+
+            let Some((head_end, body_start)) = wire::find_head_end(&self.buf) else {
+                if self.buf.len() > self.limits.max_head_length() {
+                    return Err(ParseError::TooLarge(format!(
+                        "no end of the head in {} bytes",
+                        self.buf.len()
+                    )));
+                }
+                return Ok(None);
+            };
+            let message = T::parse_head(&self.buf[..head_end], &self.limits)?;
+            let length = message.body_length(&self.limits)?.unwrap_or(0);
+            self.buf.drain(..body_start);
+            self.pending = Some((message, length));
+        }
+
+        let length = self.pending.as_ref().map_or(0, |(_, length)| *length);
+        if self.buf.len() < length {
+            return Ok(None);
+        }
+        let (mut message, _) = self.pending.take().expect("checked above");
+        let body = (length > 0).then(|| self.buf.drain(..length).collect());
+        message.complete(body)?;
+        Ok(Some(message))
+    }
+}
+
+#[derive(Debug)]
+pub struct RequestDecoder {
+    frames: Frames<Request>,
+}
+
+impl Default for RequestDecoder {
+    fn default() -> Self {
+        Self::with_limits(ParseLimits::default())
+    }
+}
+
+impl RequestDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a decoder that errors out as soon as a request is bigger than `limits` allow,
+    /// before buffering any more of it
+    pub fn with_limits(limits: ParseLimits) -> Self {
+        Self {
+            frames: Frames::new(limits),
+        }
+    }
+
+    /// appends freshly read bytes
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.frames.feed(bytes);
+    }
+
+    /// whether there's nothing buffered, not even part of a request
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// the next complete request, or None if more bytes are needed.
+    /// on a connection, the head of every request has to be terminated by an empty line.
+    /// after an error the buffer is dropped since there's no way to tell where the next
+    /// request starts, so the connection should be closed
+    pub fn next_request(&mut self) -> Result<Option<Request>, ParseError> {
+        self.frames.next()
+    }
+
+    /// parses whatever's left once the peer has stopped sending. a request without a
+    /// body doesn't need the terminating empty line here
+    pub fn finish(&mut self) -> Result<Option<Request>, ParseError> {
+        self.frames.finish()
+    }
+}
+
+/// [RequestDecoder] for the client's side, reading replies off a connection
+#[derive(Debug)]
+pub struct ResponseDecoder {
+    frames: Frames<Response>,
+}
+
+impl Default for ResponseDecoder {
+    /// without limits, like [Response::try_from]
+    fn default() -> Self {
+        Self::with_limits(ParseLimits::UNLIMITED)
+    }
+}
+
+impl ResponseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: ParseLimits) -> Self {
+        Self {
+            frames: Frames::new(limits),
+        }
+    }
+
+    /// appends freshly read bytes
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.frames.feed(bytes);
+    }
+
+    /// whether there's nothing buffered, not even part of a response
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// the next complete response, or None if more bytes are needed. errors drop the
+    /// buffer like [RequestDecoder::next_request] does
+    pub fn next_response(&mut self) -> Result<Option<Response>, ParseError> {
+        self.frames.next()
+    }
+
+    /// parses whatever's left once the server has hung up
+    pub fn finish(&mut self) -> Result<Option<Response>, ParseError> {
+        self.frames.finish()
+    }
+}
+
+fn skip_blank_lines(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|b| !matches!(b, b'\r' | b'\n'))
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+// ===== tests =====
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{RequestKind, ResponseKind, StatusCode};
+
+    const SEND: &[u8] = b"lung/a0.1 send\nto: bobby#s1\nsession: token\nlength: 2\n\nyo";
+    const CERT: &[u8] = b"lung/a0.1 certificate\n\n";
+
+    #[test]
+    fn request_split_across_chunks() {
+        let mut decoder = RequestDecoder::new();
+        for chunk in SEND.chunks(3) {
+            assert!(decoder.next_request().unwrap().is_none());
+            decoder.feed(chunk);
+        }
+        let req = decoder.next_request().unwrap().unwrap();
+        assert_eq!(req.body.as_deref(), Some(&b"yo"[..]));
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn pipelined_requests_keep_leftovers() {
+        let mut decoder = RequestDecoder::new();
+        decoder.feed(&[SEND, CERT, &SEND[..10]].concat());
+
+        let first = decoder.next_request().unwrap().unwrap();
+        assert!(matches!(first.kind, RequestKind::Send));
+        let second = decoder.next_request().unwrap().unwrap();
+        assert!(matches!(second.kind, RequestKind::Certificate));
+        assert!(decoder.next_request().unwrap().is_none());
+        assert!(!decoder.is_empty());
+
+        decoder.feed(&SEND[10..]);
+        assert!(decoder.next_request().unwrap().is_some());
+    }
+
+    #[test]
+    fn trailing_newlines_between_requests_are_skipped() {
+        let mut decoder = RequestDecoder::new();
+        decoder.feed(&[SEND, b"\r\n\n", CERT].concat());
+        assert!(decoder.next_request().unwrap().is_some());
+        assert!(decoder.next_request().unwrap().is_some());
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn finish_accepts_unterminated_head() {
+        let mut decoder = RequestDecoder::new();
+        decoder.feed(b"lung/a0.1 certificate\n");
+        assert!(decoder.next_request().unwrap().is_none());
+        assert!(decoder.finish().unwrap().is_some());
+        assert!(decoder.finish().unwrap().is_none());
+    }
+
+    #[test]
+    fn finish_rejects_truncated_body() {
+        let mut decoder = RequestDecoder::new();
+        decoder.feed(&SEND[..SEND.len() - 1]);
+        assert!(decoder.next_request().unwrap().is_none());
+        assert!(matches!(
+            decoder.finish(),
+            Err(ParseError::BodyLengthMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn error_drops_the_buffer() {
+        let mut decoder = RequestDecoder::new();
+        decoder.feed(b"lung/a0.1 nonsense\n\nlung/a0.1 certificate\n\n");
+        assert!(decoder.next_request().is_err());
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn oversized_requests_fail_before_buffering() {
+        let limits = ParseLimits {
+            max_body_length: 16,
+            max_headers: 4,
+            max_line_length: 64,
+        };
+
+        // a head that never ends
+        let mut decoder = RequestDecoder::with_limits(limits);
+        decoder.feed(b"lung/a0.1 send\n");
+        let mut result = Ok(None);
+        for _ in 0..limits.max_head_length() {
+            decoder.feed(b"x");
+            result = decoder.next_request();
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(matches!(result, Err(ParseError::TooLarge(_))));
+
+        // a body bigger than allowed is refused as soon as the head is in
+        let mut decoder = RequestDecoder::with_limits(limits);
+        decoder.feed(b"lung/a0.1 send\nto: bobby#s1\nsession: token\nlength: 17\n\n");
+        assert!(matches!(
+            decoder.next_request(),
+            Err(ParseError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn pipelined_responses_are_told_apart() {
+        let invalid = Response::build(ResponseKind::HashInvalid).finish().unwrap();
+        let teapot = Response::build(ResponseKind::Teapot)
+            .body(vec![b'\n', 0xff])
+            .finish()
+            .unwrap();
+        let stream = [invalid.to_bytes(), invalid.to_bytes(), teapot.to_bytes()].concat();
+
+        let mut decoder = ResponseDecoder::new();
+        let mut statuses = Vec::new();
+        for chunk in stream.chunks(5) {
+            decoder.feed(chunk);
+            while let Some(response) = decoder.next_response().unwrap() {
+                statuses.push(response.status);
+            }
+        }
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::HashInvalid,
+                StatusCode::HashInvalid,
+                StatusCode::Teapot
+            ]
+        );
+        assert!(decoder.is_empty());
+
+        // the contract is checked once the body is in
+        decoder.feed(b"lung/a0.1 status -60: hash not accepted\nlength: 2\n\nno");
+        assert!(matches!(
+            decoder.next_response(),
+            Err(ParseError::InvalidBody(_))
+        ));
+    }
+}
@@ -0,0 +1,182 @@
+//! every request after `certificate` travels inside an envelope, so passwords and
+//! session tokens never cross the wire in plaintext
+//! ```text
+//! lung/a0.1 encrypted
+//! length: [n]
+//!
+//! client_encrypt(server_pub, [unix timestamp, 8 bytes big endian][inner request bytes])
+//! ```
+//! the server answers with `status 2: encrypted`, its body is the inner response
+//! encrypted with a key derived from the same exchange, see
+//! [crate::shared::crypt::traffic::reply_key_from_shared]
+//!
+//! an envelope is only good within [FRESHNESS] of its timestamp, and the server
+//! remembers the ephemeral keys of the ones it opened for that long, so a captured
+//! envelope can't be sent again
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::shared::{
+    ContractError, ParseError, Request, RequestKind, Response, ResponseKind, StatusCode,
+    crypt::Timestamp,
+    crypt::traffic::{
+        client_encrypt_with_reply, decrypt_aead, encrypt_aead, server_decrypt_with_reply,
+    },
+};
+
+/// what the client keeps to read the answer to a sealed request
+pub type ReplyKey = [u8; 32];
+
+/// how far an envelope's timestamp may be from the server's clock, either way
+pub const FRESHNESS: Duration = Duration::from_secs(60);
+
+/// an envelope the server could decrypt
+pub struct Opened {
+    /// the inner request's bytes, still to be parsed
+    pub request: Vec<u8>,
+    /// what to encrypt the answer with
+    pub reply_key: ReplyKey,
+    /// the client's ephemeral key, never the same for two envelopes
+    pub ephemeral: [u8; 32],
+}
+
+/// wraps `request` for the server holding `server_pub`
+pub fn seal(request: &Request, server_pub: &PublicKey) -> (Request, ReplyKey) {
+    seal_at(request, server_pub, unix_now())
+}
+
+fn seal_at(request: &Request, server_pub: &PublicKey, at: Timestamp) -> (Request, ReplyKey) {
+    let mut plaintext = at.to_be_bytes().to_vec();
+    plaintext.extend_from_slice(&request.to_bytes());
+    let (body, key) = client_encrypt_with_reply(server_pub, &plaintext);
+    let envelope = Request {
+        version: request.version,
+        ..Request::new(RequestKind::Encrypted)
+    }
+    .body(body);
+    (envelope, key)
+}
+
+/// decrypts the envelope and checks it's fresh. whether it was opened before is up to
+/// the caller, by [Opened::ephemeral]
+pub fn open(envelope: &Request, server_secret: &StaticSecret) -> Result<Opened, ParseError> {
+    let body = envelope.body.as_deref().unwrap_or_default();
+    let (plaintext, reply_key) = server_decrypt_with_reply(server_secret, body)
+        .map_err(|e| ParseError::InvalidBody(format!("can't open the envelope: {e:?}")))?;
+    let ephemeral = body[..32]
+        .try_into()
+        .expect("it decrypted, so the key is there");
+
+    let (at, request) = plaintext
+        .split_first_chunk::<8>()
+        .ok_or_else(|| ParseError::InvalidBody("the envelope has no timestamp".into()))?;
+    let age = unix_now().abs_diff(Timestamp::from_be_bytes(*at));
+    if age > FRESHNESS.as_secs() {
+        return Err(ParseError::InvalidBody(format!(
+            "the envelope is {age}s off the server's clock"
+        )));
+    }
+    Ok(Opened {
+        request: request.to_vec(),
+        reply_key,
+        ephemeral,
+    })
+}
+
+fn unix_now() -> Timestamp {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as Timestamp)
+}
+
+/// encrypts `response` for the client that sent the envelope
+pub fn seal_reply(response: &Response, key: &ReplyKey) -> Result<Response, ContractError> {
+    Response::build(ResponseKind::Encrypted)
+        .version(response.version)
+        .body(encrypt_aead(key, &response.to_bytes()))
+        .finish()
+}
+
+/// the response inside an encrypted reply
+pub fn open_reply(reply: &Response, key: &ReplyKey) -> Result<Response, ParseError> {
+    if reply.status != StatusCode::Encrypted {
+        return Err(ParseError::InvalidBody(format!(
+            "expected an encrypted reply, got {}",
+            reply.status
+        )));
+    }
+    let body = reply.body.as_deref().unwrap_or_default();
+    let plaintext = decrypt_aead(key, body).map_err(|e| ParseError::InvalidBody(e.to_string()))?;
+    Response::try_from(plaintext.as_slice())
+}
+
+// ===== tests =====
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{HeaderKind, crypt::traffic::gen_keys};
+
+    #[test]
+    fn request_and_reply_roundtrip() {
+        let (server_secret, server_pub) = gen_keys();
+        let request = Request::new(RequestKind::HashAuth)
+            .header(HeaderKind::Client, "jerma")
+            .unwrap()
+            .header(HeaderKind::Nonce, "nonce")
+            .unwrap()
+            .header(HeaderKind::Hash, "secret")
+            .unwrap();
+        let (envelope, client_key) = seal(&request, &server_pub);
+
+        let wire = envelope.to_bytes();
+        assert!(!String::from_utf8_lossy(&wire).contains("secret"));
+
+        let envelope = Request::try_from(wire.as_slice()).unwrap();
+        let opened = open(&envelope, &server_secret).unwrap();
+        assert_eq!(
+            Request::try_from(opened.request.as_slice()).unwrap(),
+            request
+        );
+        let server_key = opened.reply_key;
+
+        let response = Response::build(ResponseKind::HashInvalid).finish().unwrap();
+        let reply = seal_reply(&response, &server_key).unwrap();
+        let reply = Response::try_from(reply.to_bytes().as_slice()).unwrap();
+        let opened = open_reply(&reply, &client_key).unwrap();
+        assert_eq!(opened.status, StatusCode::HashInvalid);
+    }
+
+    #[test]
+    fn stale_envelopes_dont_open() {
+        let (server_secret, server_pub) = gen_keys();
+        let request = Request::new(RequestKind::Info);
+        let an_hour = 60 * 60;
+        for at in [unix_now() - an_hour, unix_now() + an_hour] {
+            let (envelope, _) = seal_at(&request, &server_pub, at);
+            assert!(matches!(
+                open(&envelope, &server_secret),
+                Err(ParseError::InvalidBody(_))
+            ));
+        }
+        let (envelope, _) = seal_at(&request, &server_pub, unix_now() - 5);
+        let first = open(&envelope, &server_secret).unwrap();
+        let (other, _) = seal(&request, &server_pub);
+        assert_ne!(
+            first.ephemeral,
+            open(&other, &server_secret).unwrap().ephemeral
+        );
+    }
+
+    #[test]
+    fn wrong_keys_dont_open() {
+        let (_, server_pub) = gen_keys();
+        let (other_secret, _) = gen_keys();
+        let (envelope, _) = seal(&Request::new(RequestKind::Info), &server_pub);
+        assert!(matches!(
+            open(&envelope, &other_secret),
+            Err(ParseError::InvalidBody(_))
+        ));
+    }
+}
@@ -0,0 +1,121 @@
+//! headers in the order they were set or read, so the same message always goes on
+//! the wire the same way
+
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Headers<K> {
+    entries: Vec<(K, String)>,
+}
+
+impl<K> Default for Headers<K> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<K: PartialEq> Headers<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// sets a header. one that's already there keeps its position and gets the new value
+    pub fn insert(&mut self, key: K, value: impl Into<String>) -> Option<String> {
+        let value = value.into();
+        match self.entries.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, old)) => Some(std::mem::replace(old, value)),
+            None => {
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&String> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<String> {
+        let index = self.entries.iter().position(|(k, _)| k == key)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &String)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+}
+
+impl<K: PartialEq + Display> Headers<K> {
+    /// sorts by wire name, for output that doesn't depend on how the headers were set
+    pub fn sort_canonical(&mut self) {
+        self.entries.sort_by_cached_key(|(k, _)| k.to_string());
+    }
+}
+
+impl<'a, K: PartialEq> IntoIterator for &'a Headers<K> {
+    type Item = (&'a K, &'a String);
+    type IntoIter = Box<dyn Iterator<Item = Self::Item> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+impl<K: PartialEq> FromIterator<(K, String)> for Headers<K> {
+    fn from_iter<T: IntoIterator<Item = (K, String)>>(iter: T) -> Self {
+        let mut headers = Self::new();
+        for (k, v) in iter {
+            headers.insert(k, v);
+        }
+        headers
+    }
+}
+
+// ===== tests =====
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::ResponseHeaderKind::{self, *};
+
+    #[test]
+    fn insertion_order_is_kept() {
+        let mut headers = Headers::new();
+        headers.insert(Timestamp, "1");
+        headers.insert(Ok, "true");
+        headers.insert(MessageId, "abc");
+        headers.insert(Timestamp, "2");
+        let keys: Vec<_> = headers
+            .iter()
+            .map(|(k, v)| (k.clone(), v.as_str()))
+            .collect();
+        assert_eq!(keys, [(Timestamp, "2"), (Ok, "true"), (MessageId, "abc")]);
+    }
+
+    #[test]
+    fn canonical_sort_is_by_wire_name() {
+        let mut headers: Headers<ResponseHeaderKind> = [
+            (Timestamp, "1".into()),
+            (MessageId, "abc".into()),
+            (Ok, "true".into()),
+        ]
+        .into_iter()
+        .collect();
+        headers.sort_canonical();
+        let keys: Vec<_> = headers.iter().map(|(k, _)| k.to_string()).collect();
+        assert_eq!(keys, ["message-id", "ok", "timestamp"]);
+    }
+}
@@ -31,55 +31,128 @@ macro_rules! request_kinds {
         $variant:ident = {
             name: $name:literal,
             required: [$($header:ident),* $(,)?] // required headers
-            $(, optional: [$($optional_header:ident),* $(,)?] )? // doesnt do anything lol
-            $(, possible_responses: [$($response:ident),* $(,)?] )? // doesnt do anything either
+            $(, optional: [$($optional_header:ident),* $(,)?] )? // anything else is rejected
+            $(, possible_responses: [$($response:ident),* $(,)?] )? // asserted by the server in debug builds
+            $(, extensions: $policy:ident )? // what to do with extension headers, rejected by default
         }
     ),* $(,)?) => {
-        use HeaderKind::*;
-
-        #[derive(Debug, Clone)]
+        #[derive(Debug, Clone, PartialEq, Eq)]
         pub enum $struct_name {
-            $($variant),*
+            $($variant,)*
+            /// `!module/action`, an action declared by a module rather than the core.
+            /// the module decides which headers and responses make sense
+            Module { module: String, action: String },
         }
         // request kinds
         pub trait RequestKindSpec {
+            /// the headline name. module actions don't have a fixed one, they're written
+            /// out with Display
             fn name(&self) -> &'static str;
             fn required_headers(&self) -> &'static [HeaderKind];
             fn optional_headers(&self) -> &'static [HeaderKind];
             fn possible_responses(&self) -> &'static [ResponseKind];
+            fn extension_policy(&self) -> ExtensionPolicy;
+            fn is_module(&self) -> bool;
+
+            /// whether a standard header may appear on this kind of request. length is
+            /// part of the framing, so it's always allowed. extensions go by
+            /// [RequestKindSpec::extension_policy] instead
+            fn allows_header(&self, header: &HeaderKind) -> bool {
+                self.is_module()
+                    || *header == HeaderKind::Length
+                    || self.required_headers().contains(header)
+                    || self.optional_headers().contains(header)
+            }
+
+            /// whether this kind of request may be answered with `response`
+            fn allows_response(&self, response: ResponseKind) -> bool {
+                self.is_module()
+                    || response.is_generic_error()
+                    || self.possible_responses().contains(&response)
+            }
         }
 
         impl RequestKindSpec for $struct_name {
             fn name(&self) -> &'static str {
                 match self {
-                    $(Self::$variant => $name),*
+                    $(Self::$variant => $name,)*
+                    Self::Module { .. } => "!module",
                 }
             }
 
             fn required_headers(&self) -> &'static [HeaderKind] {
+                use HeaderKind::*;
                 match self {
-                    $(Self::$variant => &[$($header),*]),*
+                    $(Self::$variant => &[$($header),*],)*
+                    Self::Module { .. } => &[],
                 }
             }
             fn optional_headers(&self) -> &'static [HeaderKind] {
+                use HeaderKind::*;
                 match self {
-                    $(Self::$variant => &[$($($optional_header),*)?]),*
+                    $(Self::$variant => &[$($($optional_header),*)?],)*
+                    Self::Module { .. } => &[],
                 }
             }
 
             fn possible_responses(&self) -> &'static [ResponseKind] {
                 use ResponseKind::*;
                 match self {
-                    $(Self::$variant => &[$($($response),*)?]),*
+                    $(Self::$variant => &[$($($response),*)?],)*
+                    Self::Module { .. } => &[],
+                }
+            }
+
+            fn extension_policy(&self) -> ExtensionPolicy {
+                match self {
+                    $(Self::$variant => $crate::shared::meta::extension_policy!($($policy)?),)*
+                    Self::Module { .. } => ExtensionPolicy::Preserve,
+                }
+            }
+
+            fn is_module(&self) -> bool {
+                matches!(self, Self::Module { .. })
+            }
+        }
+
+        impl Display for $struct_name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    Self::Module { module, action } => write!(f, "!{module}/{action}"),
+                    other => write!(f, "{}", other.name()),
                 }
             }
         }
 
         impl $struct_name {
-            pub fn from_str(s: &str) -> Result<Self, ParseError> {
+            /// every built-in kind, in declaration order
+            pub const ALL: &'static [Self] = &[$(Self::$variant),*];
+        }
+
+        impl std::str::FromStr for $struct_name {
+            type Err = ParseError;
+
+            fn from_str(s: &str) -> Result<Self, ParseError> {
                 match s.trim().to_ascii_lowercase().as_str() {
                     $($name => Ok(Self::$variant),)*
-                    other => Err(ParseError::InvalidRequestKind(other.to_string())),
+                    other => {
+                        // module ids and actions are lowercase letters, digits, - and _
+                        let valid = |part: &str| {
+                            !part.is_empty()
+                                && part.bytes().all(|b| {
+                                    b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_'
+                                })
+                        };
+                        match other.strip_prefix('!').and_then(|rest| rest.split_once('/')) {
+                            Some((module, action)) if valid(module) && valid(action) => {
+                                Ok(Self::Module {
+                                    module: module.to_string(),
+                                    action: action.to_string(),
+                                })
+                            }
+                            _ => Err(ParseError::InvalidRequestKind(other.to_string())),
+                        }
+                    }
                 }
             }
         }
@@ -90,11 +163,37 @@ macro_rules! request_kinds {
 macro_rules! status_codes {
     ($struct_name:ident is $($name:ident = $code:literal $lexeme:literal),* $(,)?) => {
 
-        #[derive(Clone, Debug)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
         pub enum $struct_name {
             $($name = $code),*
         }
 
+        impl $struct_name {
+            /// every status, in declaration order
+            pub const ALL: &'static [Self] = &[$(Self::$name),*];
+
+            /// looks a status up by its numeric code
+            pub fn from_code(code: i32) -> Option<Self> {
+                match code {
+                    $($code => Some(Self::$name),)*
+                    _ => None,
+                }
+            }
+
+            /// the numeric code, as written in a status line
+            pub fn code(&self) -> i32 {
+                *self as i32
+            }
+        }
+
+        impl TryFrom<i32> for $struct_name {
+            type Error = ParseError;
+
+            fn try_from(code: i32) -> Result<Self, ParseError> {
+                Self::from_code(code).ok_or_else(|| ParseError::UnknownStatus(code.to_string()))
+            }
+        }
+
         impl Display for $struct_name {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                 write!(
@@ -115,9 +214,21 @@ macro_rules! status_codes {
 macro_rules! headers {
     ($struct_name:ident is $($name:ident = $lexeme:literal),* $(,)?) => {
 
-        #[derive(Debug, Hash, Eq, PartialEq, PartialOrd)]
+        #[derive(Debug, Clone, Hash, Eq, PartialEq, PartialOrd)]
         pub enum $struct_name {
-            $($name),*
+            $($name,)*
+            /// a header outside the spec, by lowercase name. what happens to it is up to
+            /// the message it's on, see [ExtensionPolicy]
+            Extension(String),
+        }
+
+        impl $struct_name {
+            /// every standard header, in declaration order
+            pub const ALL: &'static [Self] = &[$(Self::$name),*];
+
+            pub fn is_extension(&self) -> bool {
+                matches!(self, Self::Extension(_))
+            }
         }
 
         impl Display for $struct_name {
@@ -126,14 +237,20 @@ macro_rules! headers {
                     f,
                     "{}",
                     match self {
-                        $($struct_name::$name => $lexeme),*
+                        $($struct_name::$name => $lexeme,)*
+                        $struct_name::Extension(name) => name,
                     })
                 }
         }
-        impl $struct_name {
-            pub fn from_str(s: &str) -> Result<Self, ParseError> {
+        impl std::str::FromStr for $struct_name {
+            type Err = ParseError;
+
+            /// anything that isn't a standard header but looks like a header name becomes
+            /// an extension, only malformed names are errors
+            fn from_str(s: &str) -> Result<Self, ParseError> {
                 match s.to_ascii_lowercase().as_str() {
                     $($lexeme => Ok(Self::$name),)*
+                    other if $crate::shared::meta::valid_header_name(other) => Ok(Self::Extension(other.to_string())),
                     other => Err(ParseError::InvalidHeaderKey(other.to_string())),
                 }
             }
@@ -150,12 +267,21 @@ macro_rules! response_kinds {
             body: $body_req:ident
         }
     ),* $(,)?) => {
-        #[derive(Debug, Clone)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
         pub enum $struct_name {
             $($variant),*
         }
 
         impl $struct_name {
+            /// every response kind, in declaration order
+            pub const ALL: &'static [Self] = &[$(Self::$variant),*];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => stringify!($variant)),*
+                }
+            }
+
             /// numeric status code
             pub fn code(&self) -> StatusCode {
                 match self {
@@ -181,13 +307,33 @@ macro_rules! response_kinds {
             pub fn from_status(code: StatusCode) -> Result<Self, ParseError> {
                 match code {
                     $(StatusCode::$code => Ok(Self::$variant),)*
-                    _ => Err(ParseError::InvalidRequestKind(code.to_string())),
+                    #[allow(unreachable_patterns)] // only while every status has a kind
+                    _ => Err(ParseError::UnknownStatus(code.code().to_string())),
                 }
             }
         }
     };
 }
 
+// the policy a request kind declared, reject if it didn't
+macro_rules! extension_policy {
+    () => {
+        ExtensionPolicy::Reject
+    };
+    ($policy:ident) => {
+        ExtensionPolicy::$policy
+    };
+}
+
+/// header names are lowercase ascii letters, digits, dashes and underscores
+pub fn valid_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+}
+
+pub(crate) use extension_policy;
 pub(crate) use headers;
 pub(crate) use parse_errors;
 pub(crate) use request_kinds;
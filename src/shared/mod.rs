@@ -1,9 +1,23 @@
+pub mod batch;
+pub mod canonical;
+pub mod channel;
+pub mod decoder;
+pub mod envelope;
+pub mod headers;
 pub mod meta;
 pub mod request;
 pub mod response;
+pub mod spec;
 pub mod crypt;
+pub mod version;
+pub mod wire;
+pub use batch::{MessageBatch, QueuedMessage};
+pub use decoder::{RequestDecoder, ResponseDecoder};
+pub use wire::ParseLimits;
+pub use headers::Headers;
 pub use request::Request;
-pub use response::Response;
+pub use version::ProtocolVersion;
+pub use response::{ContractError, Response, ResponseBuilder};
 
 meta::headers! (
     HeaderKind is
@@ -17,6 +31,16 @@ meta::headers! (
     Pubkey = "pubkey",       // friends
     Elaboration = "elaboration",
     Encrypted = "encrypted", // is message encrypted?
+    From = "from",           // sending user, user#server
+    Sig = "sig",             // signature over the canonical record
+    MessageId = "message-id",
+    BodyHash = "body-hash",  // sha256 of the body
+    Seq = "seq",             // sequence number of a friend record
+    Record = "record",       // a signed friend record
+    At = "at",               // where a server can be reached
+    Type = "type",           // what a user notification is about
+    Server = "server",       // a server id
+    Nonce = "nonce",         // which nonce a hash auth answers
 );
 
 meta::headers! (
@@ -35,6 +59,10 @@ meta::headers! (
     Count = "count",         // number of offline messages
     From = "from",           // sender of message
     Length = "length",       // body length in bytes
+    Version = "version",     // protocol versions a server speaks
+    Nonce = "nonce",         // what the next hash auth proves the password with
+    Salt = "salt",           // the client's password salt, base64
+    Kdf = "kdf",             // which password kdf parameters go with the salt
 );
 
 meta::status_codes!(
@@ -44,10 +72,13 @@ meta::status_codes!(
 
     // 1–49: general
     MessageSent = 1 "message sent",
+    Encrypted = 2 "encrypted",
     OfflineMessages = 5 "offline messages",
 
     // 50–69: authentication / certificate
     CertificateGiven = 50 "certificate given",
+    HandshakeAccepted = 51 "handshake accepted",
+    NonceGiven = 52 "nonce given",
     HashAccepted = 60 "hash accepted",
     HashInvalid = -60 "hash not accepted",
 
@@ -55,6 +86,9 @@ meta::status_codes!(
     InternalError = -1 "internal error",
     BadRequest = -10 "bad request",
     InvalidRequestKind = -11 "invalid request kind",
+    UnsupportedVersion = -12 "unsupported version",
+    PayloadTooLarge = -13 "payload too large",
+    Timeout = -14 "timeout",
     HeaderMissing = -20 "header missing",
     HeaderInvalid = -21 "header invalid",
     HeaderEmpty = -22 "header empty",
@@ -64,16 +98,61 @@ meta::status_codes!(
     // 70–79: announcements / friend system
     AnnouncementFound = 70 "announcement found",
     FriendMade = 71 "friend made",
+    FriendRevoked = 72 "friend revoked",
     AnnouncementNotFound = -70 "announcement not found",
 );
 
+/// the ranges status codes are grouped in. errors of a range share it with its
+/// successes, the rest of the negative codes are request and internal errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusCategory {
+    /// 1–49
+    General,
+    /// 50–69, authentication and certificates
+    Auth,
+    /// 70–79, announcements and the friend system
+    Announcements,
+    /// negative codes outside the ranges above, the request went wrong or the server did
+    Error,
+    /// teapot and anything outside the ranges
+    Other,
+}
+
+impl StatusCode {
+    /// non-negative codes, teapot included
+    pub fn is_success(&self) -> bool {
+        self.code() >= 0
+    }
+
+    pub fn is_error(&self) -> bool {
+        !self.is_success()
+    }
+
+    pub fn category(&self) -> StatusCategory {
+        match self.code() {
+            1..=49 => StatusCategory::General,
+            50..=69 | -69..=-50 => StatusCategory::Auth,
+            70..=79 | -79..=-70 => StatusCategory::Announcements,
+            ..=-1 => StatusCategory::Error,
+            _ => StatusCategory::Other,
+        }
+    }
+}
+
 meta::parse_errors! {
     ParseError is
     InvalidHeaderKey => HeaderInvalid,
+    InvalidHeaderValue => HeaderInvalid,
     InvalidRequestKind => InvalidRequestKind,
     InvalidFormat => BadRequest,
     HeaderMissing => HeaderMissing,
     HeaderEmpty => HeaderEmpty,
+    BodyLengthMismatch => BadRequest,
+    InvalidBody => BadRequest,
+    UnexpectedHeader => HeaderInvalid,
+    InvalidVersion => UnsupportedVersion,
+    TooLarge => PayloadTooLarge,
+    UnknownStatus => BadRequest,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -87,22 +166,37 @@ meta::response_kinds! {
     ResponseKind is
     CertificateGiven = {
         code: CertificateGiven,
-        required: [Algo, Pubkey],
-        body: Required
+        required: [Algo, Pubkey], // algo: x25519, pubkey: base64
+        body: None
     },
     MessageSent = {
         code: MessageSent,
         required: [Ok, Timestamp, MessageId],
         body: None
     },
+    NonceGiven = {
+        code: NonceGiven,
+        required: [Nonce, Until, Salt, Kdf], // single use, for the next hash auth
+        body: None
+    },
+    HandshakeAccepted = {
+        code: HandshakeAccepted,
+        required: [Pubkey], // the server's ephemeral key, base64
+        body: None
+    },
+    Encrypted = {
+        code: Encrypted,
+        required: [], // the body is the real response, see shared::envelope
+        body: Required
+    },
     OfflineMessages = {
         code: OfflineMessages,
-        required: [Count, Timestamp, From, Length],
-        body: Required
+        required: [Count], // the body is a batch, see shared::batch
+        body: Optional
     },
     HashAccepted = {
         code: HashAccepted,
-        required: [Ok, SessionID, Until],
+        required: [Ok, Session, Until], // until is a unix timestamp
         body: None
     },
     HashInvalid = {
@@ -128,26 +222,102 @@ meta::response_kinds! {
     BadRequest = {
         code: BadRequest,
         required: [],
-        body: None
+        body: Optional
+    },
+    InvalidRequestKind = {
+        code: InvalidRequestKind,
+        required: [],
+        body: Optional
+    },
+    UnsupportedVersion = {
+        code: UnsupportedVersion,
+        required: [],
+        body: Optional
+    },
+    PayloadTooLarge = {
+        code: PayloadTooLarge,
+        required: [],
+        body: Optional
+    },
+    Timeout = {
+        code: Timeout,
+        required: [],
+        body: Optional
     },
     HeaderMissing = {
         code: HeaderMissing,
         required: [],
-        body: None
+        body: Optional
+    },
+    HeaderInvalid = {
+        code: HeaderInvalid,
+        required: [],
+        body: Optional
+    },
+    HeaderEmpty = {
+        code: HeaderEmpty,
+        required: [],
+        body: Optional
+    },
+    Unsupported = {
+        code: Unsupported,
+        required: [],
+        body: Optional
+    },
+    Denied = {
+        code: Denied,
+        required: [],
+        body: Optional
     },
     Teapot = {
         code: Teapot,
         required: [],
-        body: None
+        body: Optional
     },
     FriendMade = {
         code: FriendMade,
         required: [Pubkey],
         body: None
     },
+    FriendRevoked = {       // the revocation was taken note of
+        code: FriendRevoked,
+        required: [],
+        body: None
+    },
 
 }
 
+impl ResponseKind {
+    /// errors any request may be answered with, so they're left out of `possible_responses`
+    pub fn is_generic_error(&self) -> bool {
+        matches!(
+            self,
+            Self::InternalError
+                | Self::BadRequest
+                | Self::InvalidRequestKind
+                | Self::UnsupportedVersion
+                | Self::PayloadTooLarge
+                | Self::Timeout
+                | Self::HeaderMissing
+                | Self::HeaderInvalid
+                | Self::HeaderEmpty
+                | Self::Unsupported
+                | Self::Denied
+        )
+    }
+}
+
+/// what a request does with headers outside the spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtensionPolicy {
+    /// refuse the whole request
+    Reject,
+    /// keep them as [HeaderKind::Extension], for whoever handles the request
+    Preserve,
+    /// drop them while parsing
+    Ignore,
+}
+
 // request kinds and their possible responses, omitting internal errors
 meta::request_kinds! {
     RequestKind is
@@ -156,19 +326,41 @@ meta::request_kinds! {
         required: [],
         possible_responses: [CertificateGiven]
     },
+    Encrypted = {           // any other request, encrypted with the certificate key
+        name: "encrypted",
+        required: [Length],
+        possible_responses: [Encrypted]
+    },
+    Handshake = {           // opens a channel for the rest of the connection
+        name: "handshake",
+        required: [Pubkey],
+        possible_responses: [HandshakeAccepted]
+    },
+    Frame = {               // a request on the channel
+        name: "frame",
+        required: [Length],
+        possible_responses: [Encrypted]
+    },
     Send = {                // send message to server
         name: "send",
         required: [To, Session, Length],
+        optional: [Through],
         possible_responses: [MessageSent]
     },
     Sealed = {                // send message to server
         name: "sealed",
         required: [To, Encrypted, Length],
+        optional: [Through],
         possible_responses: [MessageSent]
     },
+    Nonce = {               // asks for the nonce the next hash auth uses
+        name: "nonce",
+        required: [Client],
+        possible_responses: [NonceGiven]
+    },
     HashAuth = {            // hash-based authentication
         name: "hash auth",
-        required: [Client, Hash],
+        required: [Client, Nonce, Hash],
         possible_responses: [HashAccepted, HashInvalid]
     },
     Refresh = {             // refresh token/session
@@ -184,31 +376,54 @@ meta::request_kinds! {
     Announcement = {        // server IP/availability update
         name: "announcement",
         required: [],
-        possible_responses: [AnnouncementFound, AnnouncementNotFound]
+        optional: [At],
+        possible_responses: [AnnouncementFound, AnnouncementNotFound],
+        extensions: Preserve
     },
     FriendRequest = {       // request friendship between servers
         name: "friend request",
-        required: [],
-        possible_responses: [FriendMade]
+        required: [From, Seq, Sig],
+        optional: [Pubkey, Elaboration],
+        possible_responses: [FriendMade],
+        extensions: Preserve
     },
     FriendMade = {          // confirm friend relationship
         name: "friend made",
-        required: [Pubkey, Elaboration],
-        possible_responses: [FriendMade]
+        required: [Record, Sig],
+        optional: [Pubkey, Elaboration],
+        possible_responses: [FriendMade],
+        extensions: Preserve
+    },
+    FriendRevoke = {        // end a friendship, signed by the revoking server
+        name: "friend revoke",
+        required: [Server, Seq, Sig],
+        possible_responses: [FriendRevoked],
+        extensions: Preserve
+    },
+    Deliver = {             // server to server message delivery
+        name: "deliver",
+        required: [From, To, Sig, Timestamp, MessageId, BodyHash, Length],
+        optional: [Through],
+        possible_responses: [MessageSent],
+        extensions: Preserve
     },
     Info = {                // anonymous info query
         name: "info",
         required: [],
-        possible_responses: [Teapot] // could add more later
+        possible_responses: [Teapot], // could add more later
+        extensions: Ignore
     },
     AuthInfo = {            // info query with session
         name: "auth info",
         required: [Session],
-        possible_responses: [Teapot, AnnouncementFound] // friends list and info
+        possible_responses: [Teapot, AnnouncementFound], // friends list and info
+        extensions: Ignore
     },
     FriendUserMoved = { // encrypted with a friend key
         name: "user announcement",
-        required: [], // client: jebediah#server2; to: jebediah#server5
-        possible_responses: [Teapot]
+        required: [],
+        optional: [Client, Type, To], // client: jebediah#server2; type: moved; to: jebediah#server5
+        possible_responses: [Teapot],
+        extensions: Preserve
     }
 }
@@ -1,21 +1,86 @@
 use crate::shared::RequestKindSpec;
-use std::collections::HashMap;
+use std::str::FromStr;
 
-use crate::shared::{HeaderKind, ParseError, RequestKind};
+use crate::shared::{
+    ExtensionPolicy, HeaderKind, Headers, ParseError, ProtocolVersion, RequestKind, meta,
+    wire::{self, ParseLimits},
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Request {
-    pub version: String,
+    pub version: ProtocolVersion,
     pub kind: RequestKind,
-    pub headers: HashMap<HeaderKind, String>,
-    pub body: Option<String>,
+    /// standard headers and, if the kind preserves them, extensions
+    pub headers: Headers<HeaderKind>,
+    pub body: Option<Vec<u8>>,
 }
 
-impl TryFrom<String> for Request {
-    type Error = ParseError;
+impl Request {
+    pub fn new(kind: RequestKind) -> Self {
+        Self {
+            version: ProtocolVersion::CURRENT,
+            kind,
+            headers: Headers::new(),
+            body: None,
+        }
+    }
+    /// adds a header, refusing anything that wouldn't parse back the same. values are
+    /// printable ascii on one line, so they can't sneak in headers of their own
+    pub fn header(
+        mut self,
+        kind: HeaderKind,
+        value: impl Into<String>,
+    ) -> Result<Self, ParseError> {
+        let value = value.into();
+        if let HeaderKind::Extension(name) = &kind
+            && !meta::valid_header_name(name)
+        {
+            return Err(ParseError::InvalidHeaderKey(format!(
+                "{name:?} isn't a header name"
+            )));
+        }
+        wire::check_header_value(&value)?;
+        self.headers.insert(kind, value);
+        Ok(self)
+    }
+    /// sets the body, an empty one is the same as none. the length header is written
+    /// by [Request::to_bytes]
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        let body = body.into();
+        self.body = (!body.is_empty()).then_some(body);
+        self
+    }
+
+    /// wire representation, parses back into the same request with [Request::try_from].
+    /// the head always ends with an empty line, so it can be sent on a connection that
+    /// stays open, and the length header always matches the body. it's left out for an
+    /// empty body, unless the kind requires it or the request already had one
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("{} {}\n", self.version, self.kind);
+        for (key, value) in &self.headers {
+            if *key != HeaderKind::Length {
+                head.push_str(&format!("{key}: {value}\n"));
+            }
+        }
+        let body = self.body.as_deref().unwrap_or_default();
+        if !body.is_empty()
+            || self.kind.required_headers().contains(&HeaderKind::Length)
+            || self.headers.contains_key(&HeaderKind::Length)
+        {
+            head.push_str(&format!("{}: {}\n", HeaderKind::Length, body.len()));
+        }
+        head.push('\n');
 
-    fn try_from(value: String) -> Result<Self, ParseError> {
-        let mut lines = value.lines();
+        let mut out = head.into_bytes();
+        out.extend_from_slice(body);
+        out
+    }
+
+    /// parses the headline and headers, leaving the body empty
+    pub(crate) fn parse_head(head: &[u8], limits: &ParseLimits) -> Result<Self, ParseError> {
+        let head = wire::head_str(head)?;
+        limits.check_head(head)?;
+        let mut lines = head.lines();
 
         // headline
         let first_line = lines
@@ -25,51 +90,368 @@ impl TryFrom<String> for Request {
         let (version, kind) = if let Some((version_str, kind_str)) = first_line.split_once(" ") {
             let kind = RequestKind::from_str(kind_str)
                 .map_err(|_| ParseError::InvalidRequestKind(kind_str.to_string()))?;
-            (version_str.to_string(), kind)
+            (ProtocolVersion::from_str(version_str)?, kind)
         } else {
             return Err(ParseError::InvalidFormat("invalid headline".into()));
         };
 
         // headers
-        let mut headers = HashMap::new();
-        for line in &mut lines {
-            let trimmed = line.trim();
-            if trimmed.is_empty() {
-                break;
-            }
-
-            // expect "key: value"
-            let (key, value) = trimmed.split_once(':').ok_or_else(|| {
-                ParseError::InvalidFormat(format!("\"{trimmed}\" is not a valid header line. headers must be formatted as [name]: [value]"))
-            })?;
-
+        let mut headers = Headers::new();
+        for line in lines {
+            let (key, value) = wire::header_line(line)?;
             let key_kind = HeaderKind::from_str(key)?;
-            let value = value.trim().to_string();
-            if value.is_empty() {
-                return Err(ParseError::HeaderEmpty(format!("header empty: {key}")));
-            };
-
-            headers.insert(key_kind, value.trim().to_string());
+            if let HeaderKind::Extension(name) = &key_kind {
+                match kind.extension_policy() {
+                    ExtensionPolicy::Reject => {
+                        return Err(ParseError::InvalidHeaderKey(format!(
+                            "{kind} requests don't take extension headers like {name}"
+                        )));
+                    }
+                    ExtensionPolicy::Ignore => continue,
+                    ExtensionPolicy::Preserve => {}
+                }
+            } else if !kind.allows_header(&key_kind) {
+                return Err(ParseError::UnexpectedHeader(format!(
+                    "{kind} requests don't take a {key_kind} header"
+                )));
+            } else if headers.contains_key(&key_kind) {
+                // two lengths could be read differently by whoever reads it next
+                return Err(ParseError::UnexpectedHeader(format!(
+                    "{key_kind} appears twice"
+                )));
+            }
+            headers.insert(key_kind, value.to_string());
         }
         for required in kind.required_headers() {
-            if !headers.contains_key(&required) {
+            if !headers.contains_key(required) {
                 return Err(ParseError::HeaderMissing(format!("{required:?}")));
             }
         }
 
-        // body
-        let body_text: String = lines.collect::<Vec<_>>().join("\n").trim().to_string();
-        let body = if !body_text.is_empty() {
-            Some(body_text)
-        } else {
-            None
-        };
-
         Ok(Request {
             kind,
             headers,
-            body: body,
+            body: None,
             version,
         })
     }
+
+    /// value of the length header, if there is one
+    pub(crate) fn body_length(&self, limits: &ParseLimits) -> Result<Option<usize>, ParseError> {
+        let Some(length) = self.headers.get(&HeaderKind::Length) else {
+            return Ok(None);
+        };
+        let length = wire::parse_length(length)?;
+        limits.check_body(length)?;
+        Ok(Some(length))
+    }
+
+    /// parses a complete request, refusing anything bigger than `limits` allow
+    pub fn parse_with_limits(value: &[u8], limits: &ParseLimits) -> Result<Self, ParseError> {
+        let (head, rest) = wire::split_head(value);
+        let mut request = Request::parse_head(head, limits)?;
+
+        // body, exactly `length` bytes after the empty line
+        request.body = wire::take_body(rest, request.body_length(limits)?)?;
+        Ok(request)
+    }
+}
+
+impl TryFrom<&[u8]> for Request {
+    type Error = ParseError;
+
+    /// parses with the default [ParseLimits]
+    fn try_from(value: &[u8]) -> Result<Self, ParseError> {
+        Request::parse_with_limits(value, &ParseLimits::default())
+    }
+}
+
+impl From<Request> for Vec<u8> {
+    fn from(request: Request) -> Self {
+        request.to_bytes()
+    }
+}
+
+// ===== tests =====
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::{ResponseKind, StatusCode};
+
+    #[test]
+    fn body_is_kept_verbatim() {
+        let body = b"  yo\r\n\r\nstill the body \n\xff\x00";
+        let mut raw = format!(
+            "lung/a0.1 send\nto: bobby#s1\nsession: token\nlength: {}\n\n",
+            body.len()
+        )
+        .into_bytes();
+        raw.extend_from_slice(body);
+        let req = Request::try_from(raw.as_slice()).unwrap();
+        assert_eq!(req.body.as_deref(), Some(&body[..]));
+    }
+
+    #[test]
+    fn crlf_head_is_accepted() {
+        let raw = b"lung/a0.1 send\r\nto: bobby#s1\r\nsession: token\r\nlength: 2\r\n\r\nyo";
+        let req = Request::try_from(&raw[..]).unwrap();
+        assert_eq!(req.headers.get(&HeaderKind::To).unwrap(), "bobby#s1");
+        assert_eq!(req.body.as_deref(), Some(&b"yo"[..]));
+    }
+
+    #[test]
+    fn body_length_must_match() {
+        let short = b"lung/a0.1 send\nto: bobby#s1\nsession: token\nlength: 3\n\nyo";
+        let long = b"lung/a0.1 send\nto: bobby#s1\nsession: token\nlength: 1\n\nyo";
+        for raw in [&short[..], &long[..]] {
+            let err = Request::try_from(raw).unwrap_err();
+            assert!(matches!(err, ParseError::BodyLengthMismatch(_)));
+        }
+    }
+
+    #[test]
+    fn body_without_length_is_rejected() {
+        let err = Request::try_from(&b"lung/a0.1 certificate\n\nyo"[..]).unwrap_err();
+        assert!(matches!(err, ParseError::HeaderMissing(_)));
+    }
+
+    #[test]
+    fn bodyless_request_parses() {
+        let req = Request::try_from(&b"lung/a0.1 certificate\n"[..]).unwrap();
+        assert!(req.body.is_none());
+        let req = Request::try_from(&b"lung/a0.1 certificate\n\n"[..]).unwrap();
+        assert!(req.body.is_none());
+    }
+
+    #[test]
+    fn non_ascii_head_is_rejected() {
+        let err = Request::try_from("lung/a0.1 send\nto: bößby#s1\n".as_bytes()).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidFormat(_)));
+    }
+
+    #[test]
+    fn headers_outside_the_spec_are_rejected() {
+        let raw = b"lung/a0.1 certificate\nsession: token\n";
+        let err = Request::try_from(&raw[..]).unwrap_err();
+        assert!(matches!(err, ParseError::UnexpectedHeader(_)));
+
+        let raw = b"lung/a0.1 send\nto: bobby#s1\nsession: token\nthrough: s2\nlength: 2\n\nyo";
+        assert!(Request::try_from(&raw[..]).is_ok());
+    }
+
+    #[test]
+    fn repeated_headers_are_rejected() {
+        let smuggled =
+            b"lung/a0.1 send\nto: bobby#s1\nsession: token\nlength: 100\nlength: 2\n\nyo";
+        let twice = b"lung/a0.1 send\nto: bobby#s1\nto: eve#s1\nsession: token\n";
+        for raw in [&smuggled[..], &twice[..]] {
+            let err = Request::try_from(raw).unwrap_err();
+            assert!(matches!(err, ParseError::UnexpectedHeader(_)), "{err:?}");
+        }
+    }
+
+    #[test]
+    fn possible_responses_include_generic_errors() {
+        assert!(RequestKind::HashAuth.allows_response(ResponseKind::HashInvalid));
+        assert!(RequestKind::HashAuth.allows_response(ResponseKind::InternalError));
+        assert!(!RequestKind::HashAuth.allows_response(ResponseKind::MessageSent));
+    }
+
+    #[test]
+    fn version_is_parsed() {
+        let req = Request::try_from(&b"0.1 certificate\n"[..]).unwrap();
+        assert_eq!(req.version.to_string(), "lung/0.1");
+        let err = Request::try_from(&b"lung/b0.1 certificate\n"[..]).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidVersion(_)));
+    }
+
+    #[test]
+    fn serializes_to_the_wire_format() {
+        let req = Request::new(RequestKind::Send)
+            .header(HeaderKind::To, "bobby#s1")
+            .unwrap()
+            .header(HeaderKind::Session, "token")
+            .unwrap()
+            .body("yo");
+        assert_eq!(
+            req.to_bytes(),
+            b"lung/a0.1 send\nto: bobby#s1\nsession: token\nlength: 2\n\nyo"
+        );
+    }
+
+    #[test]
+    fn serializer_roundtrips() {
+        let requests = [
+            Request::new(RequestKind::Certificate),
+            Request::new(RequestKind::HashAuth)
+                .header(HeaderKind::Client, "jerma")
+                .unwrap()
+                .header(HeaderKind::Nonce, "nonce")
+                .unwrap()
+                .header(HeaderKind::Hash, "abc")
+                .unwrap(),
+            Request::new(RequestKind::Sealed)
+                .header(HeaderKind::To, "u2")
+                .unwrap()
+                .header(HeaderKind::Encrypted, "true")
+                .unwrap()
+                .body(vec![0u8, b'\n', b'\n', 0xff]),
+            // kinds that require a length keep it for an empty body
+            Request::new(RequestKind::Send)
+                .header(HeaderKind::To, "x")
+                .unwrap()
+                .header(HeaderKind::Session, "y")
+                .unwrap()
+                .body(vec![]),
+            Request::try_from(&b"lung/a0.1 send\nto: x\nsession: y\nlength: 0\n\n"[..]).unwrap(),
+            Request::try_from(&b"lung/a0.1 certificate\nlength: 0\n\n"[..]).unwrap(),
+        ];
+        for req in requests {
+            let parsed = Request::try_from(req.to_bytes().as_slice()).unwrap();
+            assert_eq!(parsed.to_bytes(), req.to_bytes());
+        }
+        assert_eq!(
+            Request::new(RequestKind::Encrypted).to_bytes(),
+            b"lung/a0.1 encrypted\nlength: 0\n\n"
+        );
+    }
+
+    #[test]
+    fn header_values_cant_add_headers() {
+        for value in ["bob\nthrough: s9", "bob\r", "bößby", "", " bob", "bob\t"] {
+            let err = Request::new(RequestKind::Send)
+                .header(HeaderKind::To, value)
+                .unwrap_err();
+            assert!(
+                matches!(err, ParseError::InvalidHeaderValue(_)),
+                "{value:?}"
+            );
+        }
+        let err = Request::new(RequestKind::Module {
+            module: "stories".into(),
+            action: "post".into(),
+        })
+        .header(HeaderKind::Extension("x\nsession".into()), "token")
+        .unwrap_err();
+        assert!(matches!(err, ParseError::InvalidHeaderKey(_)));
+    }
+
+    #[test]
+    fn stale_length_header_is_replaced() {
+        let req = Request::new(RequestKind::Send)
+            .header(HeaderKind::To, "bobby#s1")
+            .unwrap()
+            .header(HeaderKind::Length, "999")
+            .unwrap()
+            .header(HeaderKind::Session, "token")
+            .unwrap()
+            .body("yo");
+        let parsed = Request::try_from(req.to_bytes().as_slice()).unwrap();
+        assert_eq!(parsed.headers.get(&HeaderKind::Length).unwrap(), "2");
+        assert_eq!(parsed.body.as_deref(), Some(&b"yo"[..]));
+    }
+
+    #[test]
+    fn limits_are_enforced() {
+        let limits = ParseLimits {
+            max_body_length: 4,
+            max_headers: 3,
+            max_line_length: 32,
+        };
+        let ok = b"lung/a0.1 send\nto: bobby#s1\nsession: token\nlength: 4\n\nyo!!";
+        assert!(Request::parse_with_limits(ok, &limits).is_ok());
+
+        let body = b"lung/a0.1 send\nto: bobby#s1\nsession: token\nlength: 5\n\nyo!!!";
+        let line = b"lung/a0.1 send\nto: bobby#s1.some.very.long.server.name\nsession: token\n";
+        let count = b"lung/a0.1 send\nto: a\nto: b\nto: c\nsession: token\n";
+        for raw in [&body[..], &line[..], &count[..]] {
+            let err = Request::parse_with_limits(raw, &limits).unwrap_err();
+            assert!(matches!(err, ParseError::TooLarge(_)), "{err:?}");
+            assert_eq!(err.to_status_code(), StatusCode::PayloadTooLarge);
+        }
+    }
+
+    #[test]
+    fn module_actions_parse() {
+        let raw = b"lung/a0.1 !stories/post\nsession: token\nCaption: hi there\nlength: 2\n\nyo";
+        let req = Request::try_from(&raw[..]).unwrap();
+        assert_eq!(
+            req.kind,
+            RequestKind::Module {
+                module: "stories".into(),
+                action: "post".into()
+            }
+        );
+        assert_eq!(req.headers.get(&HeaderKind::Session).unwrap(), "token");
+        assert_eq!(
+            req.headers
+                .get(&HeaderKind::Extension("caption".into()))
+                .unwrap(),
+            "hi there"
+        );
+
+        let reparsed = Request::try_from(req.to_bytes().as_slice()).unwrap();
+        assert_eq!(reparsed, req);
+        assert!(req.to_bytes().starts_with(b"lung/a0.1 !stories/post\n"));
+    }
+
+    #[test]
+    fn malformed_module_actions_are_rejected() {
+        for headline in [
+            "!stories",
+            "!/post",
+            "!stories/",
+            "!sto ries/post",
+            "!a/b/c",
+        ] {
+            let raw = format!("lung/a0.1 {headline}\n");
+            let err = Request::try_from(raw.as_bytes()).unwrap_err();
+            assert!(
+                matches!(err, ParseError::InvalidRequestKind(_)),
+                "{headline}"
+            );
+        }
+        // unknown headers are still refused on built-in kinds
+        let err = Request::try_from(&b"lung/a0.1 certificate\ncaption: hi\n"[..]).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidHeaderKey(_)));
+    }
+
+    #[test]
+    fn extension_policy_is_per_kind() {
+        // federation requests carry extensions along
+        let raw = b"lung/a0.1 friend revoke\nserver: s2\nseq: 18\nsig: abc\nX-Reason: gone\n";
+        let req = Request::try_from(&raw[..]).unwrap();
+        let reason = HeaderKind::Extension("x-reason".into());
+        assert_eq!(req.headers.get(&reason).unwrap(), "gone");
+        let bytes = req.to_bytes();
+        assert!(String::from_utf8_lossy(&bytes).contains("\nx-reason: gone\n"));
+
+        // info queries drop them
+        let req = Request::try_from(&b"lung/a0.1 info\nx-reason: curious\n"[..]).unwrap();
+        assert!(req.headers.is_empty());
+
+        // malformed names are never extensions
+        let err = Request::try_from(&b"lung/a0.1 info\nx reason: curious\n"[..]).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidHeaderKey(_)));
+    }
+
+    #[test]
+    fn federation_requests_parse() {
+        let raw = b"lung/a0.1 deliver\nfrom: jerma#s1\nto: bobby#s2\nsig: abc\ntimestamp: 1731515023\nmessage-id: 42\nbody-hash: def\nlength: 2\n\nyo";
+        let req = Request::try_from(&raw[..]).unwrap();
+        assert_eq!(req.kind, RequestKind::Deliver);
+        assert_eq!(req.headers.get(&HeaderKind::BodyHash).unwrap(), "def");
+        assert_eq!(req.to_bytes(), raw);
+
+        let raw = b"lung/a0.1 friend request\nfrom: s1\nseq: 1\nsig: abc\n";
+        assert!(Request::try_from(&raw[..]).is_ok());
+        // as in the readme, a signed record and nothing else
+        let raw = b"lung/a0.1 friend made\nrecord: eyJzZXJ2ZXIiOiAiczEifQ==\nsig: abc\n";
+        let req = Request::try_from(&raw[..]).unwrap();
+        assert_eq!(req.kind, RequestKind::FriendMade);
+        assert_eq!(req.headers.get(&HeaderKind::Sig).unwrap(), "abc");
+        assert!(RequestKind::FriendRevoke.allows_response(ResponseKind::FriendRevoked));
+        let raw = b"lung/a0.1 user announcement\nclient: jerma\ntype: moved\nto: jerma#s5\n";
+        assert!(Request::try_from(&raw[..]).is_ok());
+    }
 }
@@ -1,67 +1,422 @@
-use std::{collections::HashMap, fmt::Display};
+use std::str::FromStr;
 
-use crate::shared::{ResponseHeaderKind, StatusCode};
+use crate::shared::{
+    BodyRequirement, Headers, ParseError, ProtocolVersion, ResponseHeaderKind, ResponseKind,
+    StatusCode,
+    wire::{self, ParseLimits},
+};
 
 #[derive(Debug)]
 pub struct Response {
-    status: StatusCode,
-    headers: HashMap<ResponseHeaderKind, String>,
-    body: Option<String>,
+    /// the version the response is written in, the one negotiated with the client
+    pub version: ProtocolVersion,
+    pub status: StatusCode,
+    pub headers: Headers<ResponseHeaderKind>,
+    pub body: Option<Vec<u8>>,
 }
 
-impl Response {
-    pub fn new(status: StatusCode) -> Self {
-        Self {
-            status,
-            headers: HashMap::new(),
-            body: None,
+/// a response doesn't match what its [ResponseKind] promises
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContractError {
+    HeaderMissing(ResponseKind, ResponseHeaderKind),
+    BodyMissing(ResponseKind),
+    UnexpectedBody(ResponseKind),
+}
+
+impl std::fmt::Display for ContractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HeaderMissing(kind, header) => write!(f, "{kind:?} needs a {header} header"),
+            Self::BodyMissing(kind) => write!(f, "{kind:?} needs a body"),
+            Self::UnexpectedBody(kind) => write!(f, "{kind:?} can't have a body"),
+        }
+    }
+}
+
+impl From<ContractError> for ParseError {
+    fn from(err: ContractError) -> Self {
+        match err {
+            ContractError::HeaderMissing(_, header) => {
+                ParseError::HeaderMissing(format!("{header:?}"))
+            }
+            other => ParseError::InvalidBody(other.to_string()),
         }
     }
-    pub fn body(mut self, body: &str) -> Self {
-        self.body = Some(body.to_string());
-        self.header(ResponseHeaderKind::Length, body.len().to_string())
+}
+
+/// builds a response for a [ResponseKind]. nothing leaves [ResponseBuilder::finish]
+/// unless it has every required header and the body the kind asks for
+#[derive(Debug)]
+pub struct ResponseBuilder {
+    kind: ResponseKind,
+    response: Response,
+}
+
+impl ResponseBuilder {
+    pub fn header(mut self, kind: ResponseHeaderKind, value: impl Into<String>) -> Self {
+        self.response = self.response.header(kind, value);
+        self
+    }
+    pub fn version(mut self, version: ProtocolVersion) -> Self {
+        self.response.version = version;
+        self
+    }
+    /// sets the body, an empty one is the same as none. the length header is written
+    /// by [Response::to_bytes], one set by hand is ignored
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.response = self.response.body(body);
+        self
+    }
+    pub fn finish(self) -> Result<Response, ContractError> {
+        self.response.check(self.kind)?;
+        Ok(self.response)
     }
-    pub fn with_body(status: StatusCode, body: impl Into<String>) -> Self {
+}
+
+impl Response {
+    pub fn build(kind: ResponseKind) -> ResponseBuilder {
+        ResponseBuilder {
+            kind,
+            response: Response::new(kind.code()),
+        }
+    }
+
+    fn new(status: StatusCode) -> Self {
         Self {
+            version: ProtocolVersion::CURRENT,
             status,
-            headers: HashMap::new(),
-            body: Some(body.into()),
+            headers: Headers::new(),
+            body: None,
         }
     }
-    pub fn header(mut self, kind: ResponseHeaderKind, value: impl Into<String>) -> Self {
+    fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        let body = body.into();
+        self.body = (!body.is_empty()).then_some(body);
+        self
+    }
+    fn header(mut self, kind: ResponseHeaderKind, value: impl Into<String>) -> Self {
         self.headers.insert(kind, value.into());
         self
     }
-}
 
-impl Into<Vec<u8>> for Response {
-    fn into(self) -> Vec<u8> {
-        self.to_string().as_bytes().into()
+    /// checks the headers and body against what `kind` requires
+    pub fn check(&self, kind: ResponseKind) -> Result<(), ContractError> {
+        for required in kind.required_headers() {
+            if !self.headers.contains_key(required) {
+                return Err(ContractError::HeaderMissing(kind, required.clone()));
+            }
+        }
+        let body = self.body.as_deref().filter(|body| !body.is_empty());
+        match (kind.body_requirement(), body) {
+            (BodyRequirement::Required, None) => Err(ContractError::BodyMissing(kind)),
+            (BodyRequirement::None, Some(_)) => Err(ContractError::UnexpectedBody(kind)),
+            _ => Ok(()),
+        }
     }
-}
 
-impl Display for Response {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    /// wire representation. the head is ascii and always ends with an empty line, so
+    /// replies on a connection that stays open can be told apart. the length header
+    /// always matches the body, which is appended as is
+    pub fn to_bytes(&self) -> Vec<u8> {
         // headline
-        writeln!(
-            f,
-            "{} status {}: {}", // the first line could be either "v0.1 5" or "v0.1 status 5:
-            // offline messages"
-            crate::VERSION,
-            self.status.clone() as i32,
+        // the first line could be either "v0.1 5" or "v0.1 status 5: offline messages"
+        let mut out = format!(
+            "{} status {}: {}\n",
+            self.version,
+            self.status.code(),
             self.status
-        )?;
+        );
 
         // headers
         for (key, value) in &self.headers {
-            writeln!(f, "{}: {}", key, value)?;
+            if *key != ResponseHeaderKind::Length {
+                out.push_str(&format!("{key}: {value}\n"));
+            }
+        }
+        let body = self.body.as_deref().unwrap_or_default();
+        if !body.is_empty() {
+            out.push_str(&format!("{}: {}\n", ResponseHeaderKind::Length, body.len()));
+        }
+        out.push('\n');
+
+        let mut out = out.into_bytes();
+        out.extend_from_slice(body);
+        out
+    }
+
+    /// parses the headline and headers, leaving the body empty and the contract
+    /// unchecked
+    pub(crate) fn parse_head(head: &[u8], limits: &ParseLimits) -> Result<Self, ParseError> {
+        let head = wire::head_str(head)?;
+        limits.check_head(head)?;
+        let mut lines = head.lines();
+
+        // headline, either "lung/a0.1 status 5: offline messages" or "lung/a0.1 5"
+        let first_line = lines
+            .next()
+            .ok_or_else(|| ParseError::InvalidFormat("missing status".to_string()))?;
+        let (version, status_str) = first_line
+            .split_once(' ')
+            .ok_or_else(|| ParseError::InvalidFormat("invalid headline".into()))?;
+        let version = ProtocolVersion::from_str(version)?;
+        let status_str = status_str.strip_prefix("status ").unwrap_or(status_str);
+        let code_str = status_str.split(':').next().unwrap_or_default().trim();
+        let status = code_str
+            .parse::<i32>()
+            .map_err(|_| ParseError::InvalidFormat(format!("invalid status {code_str}")))?
+            .try_into()?;
+
+        // headers
+        let mut headers = Headers::new();
+        for line in lines {
+            let (key, value) = wire::header_line(line)?;
+            let key = ResponseHeaderKind::from_str(key)?;
+            if !key.is_extension() && headers.contains_key(&key) {
+                return Err(ParseError::UnexpectedHeader(format!("{key} appears twice")));
+            }
+            headers.insert(key, value.to_string());
         }
-        // body must be separated by a newline
-        if let Some(body) = &self.body {
-            writeln!(f)?;
-            writeln!(f, "{}", body)?;
+
+        Ok(Response {
+            version,
+            status,
+            headers,
+            body: None,
+        })
+    }
+
+    /// value of the length header, if there is one
+    pub(crate) fn body_length(&self, limits: &ParseLimits) -> Result<Option<usize>, ParseError> {
+        let Some(length) = self.headers.get(&ResponseHeaderKind::Length) else {
+            return Ok(None);
+        };
+        let length = wire::parse_length(length)?;
+        limits.check_body(length)?;
+        Ok(Some(length))
+    }
+
+    /// the status decides what the rest has to look like
+    pub(crate) fn check_status(&self) -> Result<(), ParseError> {
+        Ok(self.check(ResponseKind::from_status(self.status)?)?)
+    }
+
+    /// parses a complete response, refusing anything bigger than `limits` allow
+    pub fn parse_with_limits(value: &[u8], limits: &ParseLimits) -> Result<Self, ParseError> {
+        let (head, rest) = wire::split_head(value);
+        let mut response = Response::parse_head(head, limits)?;
+        response.body = wire::take_body(rest, response.body_length(limits)?)?;
+        response.check_status()?;
+        Ok(response)
+    }
+}
+
+impl TryFrom<&[u8]> for Response {
+    type Error = ParseError;
+
+    /// parses without limits, replies come from the server the client chose to talk to
+    fn try_from(value: &[u8]) -> Result<Self, ParseError> {
+        Response::parse_with_limits(value, &ParseLimits::UNLIMITED)
+    }
+}
+
+impl From<Response> for Vec<u8> {
+    fn from(response: Response) -> Self {
+        response.to_bytes()
+    }
+}
+
+// ===== tests =====
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::StatusCategory;
+
+    #[test]
+    fn binary_body_survives_serialization() {
+        let body = vec![0u8, 0xff, b'\n', b'\n', 0x80];
+        let bytes = Response::build(ResponseKind::Teapot)
+            .body(body.clone())
+            .finish()
+            .unwrap()
+            .to_bytes();
+        assert!(bytes.ends_with(&body));
+        assert!(bytes.windows(9).any(|w| w == b"length: 5"));
+    }
+
+    #[test]
+    fn wire_output_is_reproducible() {
+        let response = Response::build(ResponseKind::MessageSent)
+            .header(ResponseHeaderKind::Ok, "true")
+            .header(ResponseHeaderKind::Timestamp, "1731515023")
+            .header(ResponseHeaderKind::MessageId, "abc")
+            .finish()
+            .unwrap();
+        assert_eq!(
+            response.to_bytes(),
+            b"lung/a0.1 status 1: message sent\nok: true\ntimestamp: 1731515023\nmessage-id: abc\n\n"
+        );
+    }
+
+    #[test]
+    fn parse_roundtrip() {
+        let sent = Response::build(ResponseKind::MessageSent)
+            .header(ResponseHeaderKind::Ok, "true")
+            .header(ResponseHeaderKind::Timestamp, "1731515023")
+            .header(ResponseHeaderKind::MessageId, "abc")
+            .finish()
+            .unwrap();
+        let parsed = Response::try_from(sent.to_bytes().as_slice()).unwrap();
+        assert_eq!(parsed.status, StatusCode::MessageSent);
+        assert_eq!(parsed.headers, sent.headers);
+        assert!(parsed.body.is_none());
+
+        let sent = Response::build(ResponseKind::BadRequest)
+            .body(vec![0u8, b'\n', 0xff])
+            .finish()
+            .unwrap();
+        let parsed = Response::try_from(sent.to_bytes().as_slice()).unwrap();
+        assert_eq!(parsed.body, sent.body);
+    }
+
+    #[test]
+    fn repeated_headers_are_rejected() {
+        let raw = b"lung/a0.1 status 0: teapot status\nlength: 100\nlength: 2\n\nyo";
+        assert!(matches!(
+            Response::try_from(&raw[..]),
+            Err(ParseError::UnexpectedHeader(_))
+        ));
+    }
+
+    #[test]
+    fn short_headline_parses() {
+        let parsed = Response::try_from(&b"lung/a0.1 -60\n"[..]).unwrap();
+        assert_eq!(parsed.status, StatusCode::HashInvalid);
+        let parsed = Response::try_from(&b"lung/a0.1 status -60: hash not accepted\n"[..]).unwrap();
+        assert_eq!(parsed.status, StatusCode::HashInvalid);
+    }
+
+    #[test]
+    fn extension_headers_are_kept() {
+        let raw = b"lung/a0.1 status -60: hash not accepted\nretry-after: 30\n\n";
+        let parsed = Response::try_from(&raw[..]).unwrap();
+        let retry = ResponseHeaderKind::Extension("retry-after".into());
+        assert_eq!(parsed.headers.get(&retry).unwrap(), "30");
+        assert_eq!(parsed.to_bytes(), raw);
+    }
+
+    #[test]
+    fn status_codes_roundtrip_as_numbers() {
+        for code in -100..100 {
+            if let Ok(status) = StatusCode::try_from(code) {
+                assert_eq!(status.code(), code);
+                assert_eq!(status.is_error(), code < 0);
+            }
         }
+        assert!(matches!(
+            StatusCode::try_from(12345),
+            Err(ParseError::UnknownStatus(_))
+        ));
+        assert_eq!(StatusCode::MessageSent.category(), StatusCategory::General);
+        for error in [
+            StatusCode::InternalError,
+            StatusCode::BadRequest,
+            StatusCode::Timeout,
+            StatusCode::HeaderEmpty,
+            StatusCode::Unsupported,
+            StatusCode::Denied,
+        ] {
+            assert_eq!(error.category(), StatusCategory::Error, "{error}");
+        }
+        assert_eq!(StatusCode::HashInvalid.category(), StatusCategory::Auth);
+        assert_eq!(
+            StatusCode::AnnouncementNotFound.category(),
+            StatusCategory::Announcements
+        );
+        assert_eq!(StatusCode::Teapot.category(), StatusCategory::Other);
+        assert!(StatusCode::Teapot.is_success());
+    }
+
+    #[test]
+    fn unknown_status_is_rejected() {
+        assert!(matches!(
+            Response::try_from(&b"lung/a0.1 status 12345: what\n"[..]),
+            Err(ParseError::UnknownStatus(_))
+        ));
+        assert!(Response::try_from(&b"lung/a0.1 status\n"[..]).is_err());
+    }
+
+    #[test]
+    fn response_kind_contract_is_checked() {
+        let missing = b"lung/a0.1 status 50: certificate given\nalgo: x25519\n";
+        assert!(matches!(
+            Response::try_from(&missing[..]),
+            Err(ParseError::HeaderMissing(_))
+        ));
+        let body = b"lung/a0.1 status -60: hash not accepted\nlength: 2\n\nno";
+        assert!(matches!(
+            Response::try_from(&body[..]),
+            Err(ParseError::InvalidBody(_))
+        ));
+    }
+
+    #[test]
+    fn length_always_matches_the_body() {
+        let response = Response::build(ResponseKind::Teapot)
+            .body("yo")
+            .header(ResponseHeaderKind::Length, "999")
+            .finish()
+            .unwrap();
+        let parsed = Response::try_from(response.to_bytes().as_slice()).unwrap();
+        assert_eq!(
+            parsed.headers.get(&ResponseHeaderKind::Length).unwrap(),
+            "2"
+        );
+        assert_eq!(parsed.body.as_deref(), Some(&b"yo"[..]));
+
+        // an empty body is no body, when building and when parsing
+        let empty = Response::build(ResponseKind::Encrypted)
+            .body(vec![])
+            .finish();
+        assert_eq!(
+            empty.unwrap_err(),
+            ContractError::BodyMissing(ResponseKind::Encrypted)
+        );
+        let response = Response::build(ResponseKind::HashInvalid)
+            .body(vec![])
+            .finish()
+            .unwrap();
+        assert!(Response::try_from(response.to_bytes().as_slice()).is_ok());
+    }
+
+    #[test]
+    fn builder_enforces_contract() {
+        let missing = Response::build(ResponseKind::CertificateGiven)
+            .header(ResponseHeaderKind::Algo, "x25519")
+            .finish();
+        assert_eq!(
+            missing.unwrap_err(),
+            ContractError::HeaderMissing(
+                ResponseKind::CertificateGiven,
+                ResponseHeaderKind::Pubkey
+            )
+        );
+
+        let unexpected = Response::build(ResponseKind::HashInvalid)
+            .body("no")
+            .finish();
+        assert_eq!(
+            unexpected.unwrap_err(),
+            ContractError::UnexpectedBody(ResponseKind::HashInvalid)
+        );
 
-        Ok(())
+        let certificate = Response::build(ResponseKind::CertificateGiven)
+            .header(ResponseHeaderKind::Algo, "x25519")
+            .header(ResponseHeaderKind::Pubkey, "key")
+            .body("hi")
+            .finish();
+        assert_eq!(
+            certificate.unwrap_err(),
+            ContractError::UnexpectedBody(ResponseKind::CertificateGiven)
+        );
     }
 }
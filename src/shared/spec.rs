@@ -0,0 +1,224 @@
+//! the protocol as the code defines it, written out for client authors and the docs.
+//! everything here comes from the tables in shared/mod.rs, so it can't drift from
+//! what the parser actually accepts. `lung spec json` and `lung spec markdown` print it
+
+use std::fmt::Write;
+
+use crate::shared::{
+    HeaderKind, ProtocolVersion, RequestKind, RequestKindSpec, ResponseHeaderKind, ResponseKind,
+    StatusCode,
+};
+
+/// the whole spec as a json object
+pub fn json() -> String {
+    let mut out = String::from("{\n");
+    let _ = writeln!(
+        out,
+        "  \"version\": {},",
+        quote(&ProtocolVersion::CURRENT.to_string())
+    );
+    let _ = writeln!(
+        out,
+        "  \"request_headers\": {},",
+        list(HeaderKind::ALL.iter().map(ToString::to_string))
+    );
+    let _ = writeln!(
+        out,
+        "  \"response_headers\": {},",
+        list(ResponseHeaderKind::ALL.iter().map(ToString::to_string))
+    );
+
+    out.push_str("  \"status_codes\": [\n");
+    let statuses: Vec<_> = StatusCode::ALL
+        .iter()
+        .map(|status| {
+            format!(
+                "    {{ \"code\": {}, \"text\": {}, \"category\": {} }}",
+                status.code(),
+                quote(&status.to_string()),
+                quote(&lowercase(status.category()))
+            )
+        })
+        .collect();
+    out.push_str(&statuses.join(",\n"));
+    out.push_str("\n  ],\n");
+
+    out.push_str("  \"requests\": [\n");
+    let requests: Vec<_> = RequestKind::ALL
+        .iter()
+        .map(|kind| {
+            format!(
+                "    {{ \"name\": {}, \"required_headers\": {}, \"optional_headers\": {}, \"extensions\": {}, \"possible_responses\": {} }}",
+                quote(kind.name()),
+                list(kind.required_headers().iter().map(ToString::to_string)),
+                list(kind.optional_headers().iter().map(ToString::to_string)),
+                quote(&lowercase(kind.extension_policy())),
+                list(kind.possible_responses().iter().map(|r| r.name().to_string())),
+            )
+        })
+        .collect();
+    out.push_str(&requests.join(",\n"));
+    out.push_str("\n  ],\n");
+
+    out.push_str("  \"responses\": [\n");
+    let responses: Vec<_> = ResponseKind::ALL
+        .iter()
+        .map(|kind| {
+            format!(
+                "    {{ \"name\": {}, \"status\": {}, \"required_headers\": {}, \"body\": {}, \"generic_error\": {} }}",
+                quote(kind.name()),
+                kind.code().code(),
+                list(kind.required_headers().iter().map(ToString::to_string)),
+                quote(&lowercase(kind.body_requirement())),
+                kind.is_generic_error(),
+            )
+        })
+        .collect();
+    out.push_str(&responses.join(",\n"));
+    out.push_str("\n  ]\n}\n");
+    out
+}
+
+/// the whole spec as markdown tables
+pub fn markdown() -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# lung protocol, {}\n", ProtocolVersion::CURRENT);
+    out.push_str("generated by `lung spec markdown`, don't edit by hand\n\n");
+
+    out.push_str("## requests\n\n");
+    out.push_str(
+        "| request | required headers | optional headers | extension headers | responses |\n",
+    );
+    out.push_str("|---|---|---|---|---|\n");
+    for kind in RequestKind::ALL {
+        let _ = writeln!(
+            out,
+            "| `{}` | {} | {} | {} | {} |",
+            kind.name(),
+            code_list(kind.required_headers().iter().map(ToString::to_string)),
+            code_list(kind.optional_headers().iter().map(ToString::to_string)),
+            lowercase(kind.extension_policy()),
+            code_list(
+                kind.possible_responses()
+                    .iter()
+                    .map(|r| r.name().to_string())
+            ),
+        );
+    }
+    out.push_str(
+        "\n`length` may be on any request and is required whenever there's a body. \
+         every request may also be answered with a generic error. \
+         `!module/action` requests are defined by modules, they take any header\n\n",
+    );
+
+    out.push_str("## responses\n\n");
+    out.push_str("| response | status | required headers | body | generic error |\n");
+    out.push_str("|---|---|---|---|---|\n");
+    for kind in ResponseKind::ALL {
+        let _ = writeln!(
+            out,
+            "| `{}` | {} | {} | {} | {} |",
+            kind.name(),
+            kind.code().code(),
+            code_list(kind.required_headers().iter().map(ToString::to_string)),
+            lowercase(kind.body_requirement()),
+            if kind.is_generic_error() { "yes" } else { "" },
+        );
+    }
+
+    out.push_str("\n## status codes\n\n");
+    out.push_str("| code | text | category |\n");
+    out.push_str("|---|---|---|\n");
+    for status in StatusCode::ALL {
+        let _ = writeln!(
+            out,
+            "| {} | {} | {} |",
+            status.code(),
+            status,
+            lowercase(status.category())
+        );
+    }
+
+    out.push_str("\n## headers\n\n");
+    let _ = writeln!(
+        out,
+        "request: {}\n",
+        code_list(HeaderKind::ALL.iter().map(ToString::to_string))
+    );
+    let _ = writeln!(
+        out,
+        "response: {}",
+        code_list(ResponseHeaderKind::ALL.iter().map(ToString::to_string))
+    );
+    out
+}
+
+fn lowercase(value: impl std::fmt::Debug) -> String {
+    format!("{value:?}").to_ascii_lowercase()
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn list(items: impl Iterator<Item = String>) -> String {
+    let items: Vec<_> = items.map(|item| quote(&item)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+fn code_list(items: impl Iterator<Item = String>) -> String {
+    let items: Vec<_> = items.map(|item| format!("`{item}`")).collect();
+    items.join(", ")
+}
+
+// ===== tests =====
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn everything_is_listed() {
+        let (json, markdown) = (json(), markdown());
+        for kind in RequestKind::ALL {
+            assert!(json.contains(&quote(kind.name())), "{kind}");
+            assert!(
+                markdown.contains(&format!("| `{}` |", kind.name())),
+                "{kind}"
+            );
+        }
+        for kind in ResponseKind::ALL {
+            assert!(json.contains(&quote(kind.name())), "{kind:?}");
+        }
+        for status in StatusCode::ALL {
+            assert!(json.contains(&format!("\"code\": {},", status.code())));
+        }
+    }
+
+    #[test]
+    fn json_is_balanced() {
+        let json = json();
+        let count = |c| json.chars().filter(|x| *x == c).count();
+        assert_eq!(count('{'), count('}'));
+        assert_eq!(count('['), count(']'));
+        assert_eq!(count('"') % 2, 0);
+        assert_eq!(quote("a\"b\\\n"), "\"a\\\"b\\\\\\u000a\"");
+    }
+
+    #[test]
+    fn docs_are_up_to_date() {
+        // regenerate with `cargo run -- spec markdown > docs/spec.md`
+        assert_eq!(include_str!("../../docs/spec.md"), markdown());
+    }
+}
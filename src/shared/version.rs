@@ -0,0 +1,144 @@
+//! protocol versions as they appear in headlines, `lung/a0.1`, `a0.1` or a bare `0.1`.
+//! until 1.0 every minor version is its own protocol. from then on a server speaks
+//! every older minor of its major version, so it can answer old clients in their version
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::shared::ParseError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Stage {
+    Alpha,
+    Release,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProtocolVersion {
+    pub stage: Stage,
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl ProtocolVersion {
+    pub const CURRENT: Self = Self {
+        stage: Stage::Alpha,
+        major: 0,
+        minor: 1,
+    };
+
+    /// everything the standard implementation can speak
+    pub const SUPPORTED: &'static [Self] = &[Self::CURRENT];
+
+    /// whether a peer speaking `self` can talk to one speaking `other`.
+    /// the stage is only a label, `0.1` and `a0.1` are the same protocol
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        if self.major == 0 || other.major == 0 {
+            self.major == other.major && self.minor == other.minor
+        } else {
+            self.major == other.major
+        }
+    }
+
+    /// picks the version to answer a client speaking `self` with, if any of `supported` can.
+    /// an exact match answers in the supported version. otherwise, if a compatible newer
+    /// version is supported, the server speaks down to the client in the client's version
+    pub fn negotiate(&self, supported: &[Self]) -> Option<Self> {
+        if let Some(exact) = supported
+            .iter()
+            .find(|v| v.major == self.major && v.minor == self.minor)
+        {
+            return Some(*exact);
+        }
+        supported
+            .iter()
+            .any(|v| v.is_compatible(self) && v.minor > self.minor)
+            .then_some(*self)
+    }
+}
+
+impl Default for ProtocolVersion {
+    fn default() -> Self {
+        Self::CURRENT
+    }
+}
+
+impl Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stage = match self.stage {
+            Stage::Alpha => "a",
+            Stage::Release => "",
+        };
+        write!(f, "lung/{stage}{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for ProtocolVersion {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let invalid = || ParseError::InvalidVersion(format!("{s} is not a lung version"));
+
+        let rest = s.strip_prefix("lung/").unwrap_or(s);
+        let (stage, rest) = match rest.strip_prefix('a') {
+            Some(rest) => (Stage::Alpha, rest),
+            None => (Stage::Release, rest),
+        };
+        let (major, minor) = rest.split_once('.').ok_or_else(invalid)?;
+        let number = |n: &str| {
+            if n.is_empty() || !n.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            n.parse::<u16>().map_err(|_| invalid())
+        };
+
+        Ok(Self {
+            stage,
+            major: number(major)?,
+            minor: number(minor)?,
+        })
+    }
+}
+
+// ===== tests =====
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> ProtocolVersion {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn headline_forms_parse() {
+        assert_eq!(v("lung/a0.1"), ProtocolVersion::CURRENT);
+        assert_eq!(v("a0.1"), ProtocolVersion::CURRENT);
+        assert_eq!(v("0.1").stage, Stage::Release);
+        assert_eq!(v("lung/1.12").minor, 12);
+        assert_eq!(v("lung/a0.1").to_string(), "lung/a0.1");
+
+        for bad in [
+            "", "lung/", "lung/a0", "0.x", "1.-1", "v0.1", "0.1.2", "0.99999",
+        ] {
+            assert!(bad.parse::<ProtocolVersion>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn pre_release_minors_dont_mix() {
+        assert_eq!(
+            v("0.1").negotiate(ProtocolVersion::SUPPORTED),
+            Some(ProtocolVersion::CURRENT)
+        );
+        assert_eq!(v("a0.2").negotiate(ProtocolVersion::SUPPORTED), None);
+        assert_eq!(v("a0.1").negotiate(&[v("a0.2")]), None);
+    }
+
+    #[test]
+    fn mature_servers_speak_down() {
+        let supported = [v("1.3"), v("2.0")];
+        assert_eq!(v("1.1").negotiate(&supported), Some(v("1.1")));
+        assert_eq!(v("1.3").negotiate(&supported), Some(v("1.3")));
+        assert_eq!(v("1.4").negotiate(&supported), None);
+        assert_eq!(v("3.0").negotiate(&supported), None);
+    }
+}
@@ -0,0 +1,161 @@
+//! byte-level helpers shared by everything that reads lung messages off the wire.
+//! the headline and headers are ascii text, the body is opaque bytes
+
+use crate::shared::ParseError;
+
+/// how much of a request the parser is willing to look at before giving up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// largest body a length header may announce, the `max-length` of `info`
+    pub max_body_length: usize,
+    pub max_headers: usize,
+    /// longest headline or header line in bytes, without the line break
+    pub max_line_length: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            max_body_length: 64000,
+            max_headers: 32,
+            max_line_length: 1024,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// no limits at all, for messages from a peer that's trusted not to flood
+    pub const UNLIMITED: ParseLimits = ParseLimits {
+        max_body_length: usize::MAX,
+        max_headers: usize::MAX,
+        max_line_length: usize::MAX,
+    };
+
+    /// the most bytes a head can take up, line breaks included
+    pub fn max_head_length(&self) -> usize {
+        self.max_headers
+            .saturating_add(1)
+            .saturating_mul(self.max_line_length.saturating_add(2))
+    }
+
+    pub fn check_head(&self, head: &str) -> Result<(), ParseError> {
+        for (i, line) in head.lines().enumerate() {
+            if i > self.max_headers {
+                return Err(ParseError::TooLarge(format!(
+                    "more than {} headers",
+                    self.max_headers
+                )));
+            }
+            if line.len() > self.max_line_length {
+                return Err(ParseError::TooLarge(format!(
+                    "line {} is longer than {} bytes",
+                    i + 1,
+                    self.max_line_length
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check_body(&self, length: usize) -> Result<(), ParseError> {
+        if length > self.max_body_length {
+            return Err(ParseError::TooLarge(format!(
+                "body of {length} bytes, the limit is {}",
+                self.max_body_length
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// finds the first empty line. returns where the head ends and where the body starts
+pub fn find_head_end(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut start = 0;
+    while let Some(end) = bytes[start..].iter().position(|&b| b == b'\n') {
+        let line = &bytes[start..start + end];
+        if line.is_empty() || line == b"\r" {
+            return Some((start, start + end + 1));
+        }
+        start += end + 1;
+    }
+    None
+}
+
+/// splits a message at the first empty line. the head has no trailing line break,
+/// the body (if there's an empty line at all) is returned untouched
+pub fn split_head(bytes: &[u8]) -> (&[u8], Option<&[u8]>) {
+    match find_head_end(bytes) {
+        Some((head_end, body_start)) => (&bytes[..head_end], Some(&bytes[body_start..])),
+        None => (bytes, None),
+    }
+}
+
+/// the head of a message must be plain ascii
+pub fn head_str(head: &[u8]) -> Result<&str, ParseError> {
+    if !head.is_ascii() {
+        return Err(ParseError::InvalidFormat(
+            "headline and headers must be ascii".into(),
+        ));
+    }
+    // ascii is always valid utf-8
+    std::str::from_utf8(head).map_err(|e| ParseError::InvalidFormat(e.to_string()))
+}
+
+/// splits a "key: value" header line, rejecting empty values
+pub fn header_line(line: &str) -> Result<(&str, &str), ParseError> {
+    let trimmed = line.trim();
+    let (key, value) = trimmed.split_once(':').ok_or_else(|| {
+        ParseError::InvalidFormat(format!(
+            "\"{trimmed}\" is not a valid header line. headers must be formatted as [name]: [value]"
+        ))
+    })?;
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(ParseError::HeaderEmpty(format!("header empty: {key}")));
+    }
+    Ok((key, value))
+}
+
+/// whether `value` comes back the same after a trip through [header_line]. it has to be
+/// printable ascii on one line, without whitespace around it
+pub fn check_header_value(value: &str) -> Result<(), ParseError> {
+    let printable = value.bytes().all(|b| b.is_ascii_graphic() || b == b' ');
+    if value.is_empty() || !printable || value.trim() != value {
+        return Err(ParseError::InvalidHeaderValue(format!(
+            "{value:?} can't be sent as a header value"
+        )));
+    }
+    Ok(())
+}
+
+/// parses the value of a length header
+pub fn parse_length(value: &str) -> Result<usize, ParseError> {
+    value.parse::<usize>().map_err(|_| {
+        ParseError::InvalidHeaderValue(format!("length must be a byte count, got {value}"))
+    })
+}
+
+/// takes the body out of whatever followed the head. it has to be exactly `length` bytes,
+/// and anything without a length header must not have a body at all
+pub fn take_body(
+    rest: Option<&[u8]>,
+    length: Option<usize>,
+) -> Result<Option<Vec<u8>>, ParseError> {
+    let rest = rest.unwrap_or(&[]);
+    let length = match length {
+        Some(length) => length,
+        None if rest.is_empty() => 0,
+        None => {
+            return Err(ParseError::HeaderMissing(
+                "a body was sent without a length header".into(),
+            ));
+        }
+    };
+    if rest.len() != length {
+        return Err(ParseError::BodyLengthMismatch(format!(
+            "length says {length} bytes, got {}",
+            rest.len()
+        )));
+    }
+    Ok((length > 0).then(|| rest.to_vec()))
+}
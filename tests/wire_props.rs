@@ -0,0 +1,209 @@
+//! property tests for everything that reads bytes off the wire. the same checks run
+//! under libfuzzer in fuzz/, these run offline with plain `cargo test`
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use lung::shared::{
+    HeaderKind, MessageBatch, Request, RequestDecoder, RequestKind, RequestKindSpec, Response,
+    ResponseDecoder,
+};
+use proptest::{prelude::*, sample::subsequence};
+
+// ===== allocation accounting =====
+// counts the bytes each thread asks for, so a test can tell how much parsing allocated
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|a| a.set(a.get() + layout.size()));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|a| a.set(a.get() + new_size));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// bytes allocated by `f` on this thread
+fn allocated_by(f: impl FnOnce()) -> usize {
+    let before = ALLOCATED.with(Cell::get);
+    f();
+    ALLOCATED.with(Cell::get) - before
+}
+
+// ===== strategies =====
+
+fn header_value() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9#!._/-][a-zA-Z0-9#!._/: -]{0,30}[a-zA-Z0-9#!._/-]|[a-zA-Z0-9]"
+}
+
+/// a request that follows its kind's spec: every required header, some optional ones
+fn valid_request() -> impl Strategy<Value = Request> {
+    proptest::sample::select(RequestKind::ALL)
+        .prop_flat_map(|kind| {
+            let optional = kind.optional_headers().to_vec();
+            let len = optional.len();
+            (
+                Just(kind),
+                subsequence(optional, 0..=len),
+                proptest::collection::vec(header_value(), 16),
+                prop_oneof![
+                    Just(Vec::new()),
+                    proptest::collection::vec(any::<u8>(), 1..256)
+                ],
+            )
+        })
+        .prop_map(|(kind, optional, values, body)| {
+            let headers = kind
+                .required_headers()
+                .iter()
+                .chain(optional.iter())
+                .filter(|h| **h != HeaderKind::Length)
+                .cloned()
+                .collect::<Vec<_>>();
+            let mut request = Request::new(kind);
+            for (header, value) in headers.into_iter().zip(values) {
+                request = request.header(header, value).unwrap();
+            }
+            request.body(body)
+        })
+}
+
+// ===== properties =====
+
+proptest! {
+    #[test]
+    fn serialize_parse_roundtrip(request in valid_request()) {
+        let bytes = request.to_bytes();
+        let parsed = Request::try_from(bytes.as_slice()).unwrap();
+        prop_assert_eq!(&parsed.kind, &request.kind);
+        prop_assert_eq!(&parsed.body, &request.body);
+        for (key, value) in &request.headers {
+            prop_assert_eq!(parsed.headers.get(key), Some(value));
+        }
+        prop_assert_eq!(parsed.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn header_values_either_roundtrip_or_are_refused(
+        value in "[ -~\r\n\t\u{80}-\u{10ffff}]{0,24}",
+    ) {
+        let built = Request::new(RequestKind::Certificate).header(HeaderKind::To, value.clone());
+        let unsafe_value = value.is_empty()
+            || value.trim() != value
+            || value.chars().any(|c| c.is_control() || !c.is_ascii());
+        match built {
+            Ok(request) => {
+                prop_assert!(!unsafe_value, "{:?} was accepted", value);
+                // the kind doesn't take a to header, the head still has to come back whole
+                let text = String::from_utf8(request.to_bytes()).unwrap();
+                prop_assert_eq!(text, format!("lung/a0.1 certificate\nto: {value}\n\n"));
+                let send = Request::new(RequestKind::Send)
+                    .header(HeaderKind::To, value.clone())
+                    .and_then(|r| r.header(HeaderKind::Session, "token"))
+                    .unwrap();
+                let parsed = Request::try_from(send.to_bytes().as_slice()).unwrap();
+                prop_assert_eq!(parsed.headers.get(&HeaderKind::To), Some(&value));
+                prop_assert_eq!(parsed.to_bytes(), send.to_bytes());
+            }
+            Err(_) => prop_assert!(unsafe_value, "{:?} was refused", value),
+        }
+    }
+
+    #[test]
+    fn decoder_agrees_with_parser(
+        requests in proptest::collection::vec(valid_request(), 1..4),
+        chunk in 1usize..64,
+    ) {
+        let stream: Vec<u8> = requests.iter().flat_map(Request::to_bytes).collect();
+        let mut decoder = RequestDecoder::new();
+        let mut decoded = Vec::new();
+        for piece in stream.chunks(chunk) {
+            decoder.feed(piece);
+            while let Some(request) = decoder.next_request().unwrap() {
+                decoded.push(request.to_bytes());
+            }
+        }
+        let expected: Vec<_> = requests.iter().map(Request::to_bytes).collect();
+        prop_assert_eq!(decoded, expected);
+        prop_assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic(bytes in proptest::collection::vec(any::<u8>(), 0..512)) {
+        let _ = Request::try_from(bytes.as_slice());
+        let _ = Response::try_from(bytes.as_slice());
+        let _ = MessageBatch::decode(&bytes, 3);
+
+        let mut decoder = RequestDecoder::new();
+        for piece in bytes.chunks(7) {
+            decoder.feed(piece);
+            while let Ok(Some(_)) = decoder.next_request() {}
+        }
+        let _ = decoder.finish();
+
+        let mut decoder = ResponseDecoder::new();
+        for piece in bytes.chunks(7) {
+            decoder.feed(piece);
+            while let Ok(Some(_)) = decoder.next_response() {}
+        }
+        let _ = decoder.finish();
+    }
+
+    #[test]
+    fn mutated_requests_never_panic(
+        request in valid_request(),
+        flips in proptest::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..8),
+    ) {
+        let mut bytes = request.to_bytes();
+        for (index, byte) in flips {
+            let i = index.index(bytes.len());
+            bytes[i] = byte;
+        }
+        let _ = Request::try_from(bytes.as_slice());
+    }
+
+    #[test]
+    fn allocation_is_bounded_by_input(
+        request in valid_request(),
+        claimed in prop_oneof![Just(usize::MAX), Just(u32::MAX as usize), 0usize..1 << 40],
+    ) {
+        // a length header can claim anything, only the bytes that arrived may be allocated
+        let head = format!("lung/a0.1 {}\nlength: {claimed}\n\n", request.kind);
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(request.body.as_deref().unwrap_or_default());
+
+        let budget = 16 * 1024 + 8 * bytes.len();
+        let parse = allocated_by(|| {
+            let _ = Request::try_from(bytes.as_slice());
+        });
+        let decode = allocated_by(|| {
+            let mut decoder = RequestDecoder::new();
+            decoder.feed(&bytes);
+            let _ = decoder.next_request();
+            let _ = decoder.finish();
+        });
+        let batch = allocated_by(|| {
+            let _ = MessageBatch::decode(&bytes, usize::MAX);
+        });
+        prop_assert!(parse < budget, "parser allocated {} bytes", parse);
+        prop_assert!(decode < budget, "decoder allocated {} bytes", decode);
+        prop_assert!(batch < budget, "batch decoder allocated {} bytes", batch);
+    }
+}